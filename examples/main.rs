@@ -50,10 +50,11 @@ fn main() {
     let mut delay = Delay::new(p.SYST, clocks);
 
     let mut counter : u8 = 0;
+    let mut buffer = [0; 128];
     loop {
         rx.clear_overrun_error();
         led.set_high();
-        let r = serializer::read(&mut || rx.read(), &mut |res| {
+        let r = serializer::read_into(&mut || rx.read(), &mut |res| {
             led.set_low();
             match res {
             | frame::Inbound::RxPacket { ref data, .. } => {
@@ -72,7 +73,7 @@ fn main() {
             | _ => ()
             }
             iprintln!(&mut itm.stim[0], "Recived frame: {:?}", res);
-        });
+        }, serializer::ApiMode::Unescaped, &mut buffer);
         iprintln!(&mut itm.stim[0], "Read: {:?}", r);
     }
 }
@@ -35,6 +35,8 @@ app! {
         static COUNTER: u32;
         static TX: Tx<USART2>;
         static RX: Rx<USART2>;
+        static DECODER: serializer::FrameDecoder<'static>;
+        static BUFFER: [u8; 128] = [0; 128];
         static ITM: ITM;
         static TIMER: Timer<TIM6>;
         static DTO: Option<i64>;
@@ -45,7 +47,7 @@ app! {
         USART2_EXTI26: {
             path: receive,
             priority: 2,
-            resources: [RX, ITM, DTO],
+            resources: [RX, DECODER, ITM, DTO],
         },
         TIM6_DACUNDER: {
             path: on_timer,
@@ -54,12 +56,16 @@ app! {
         }
     },
 
+    init: {
+        resources: [BUFFER],
+    },
+
     idle: {
         resources: [ITM]
     },
 }
 
-fn init(p: init::Peripherals) -> init::LateResources {
+fn init(p: init::Peripherals, r: init::Resources) -> init::LateResources {
     let mut flash = p.device.FLASH.constrain();
     let mut rcc = p.device.RCC.constrain();
     let mut gpioa = p.device.GPIOA.split(&mut rcc.ahb);
@@ -88,6 +94,7 @@ fn init(p: init::Peripherals) -> init::LateResources {
     
     init::LateResources { COUNTER: 0,
         TX: tx, RX: rx,
+        DECODER: serializer::FrameDecoder::new(serializer::ApiMode::Unescaped, r.BUFFER),
         ITM: itm, TIMER: timer,
         DTO: None, TEMP: 0 }
 }
@@ -133,9 +140,10 @@ fn on_timer(t: &mut Threshold, mut r: TIM6_DACUNDER::Resources) {
 }
 
 fn receive(_t: &mut Threshold, r: USART2_EXTI26::Resources) {
-    let (mut rx, mut itm, mut dto) = (r.RX, r.ITM, r.DTO);
-    if rx.has_data() {
-        serializer::read(&mut || rx.read(), &mut |res| {
+    let (mut rx, mut decoder, mut itm, mut dto) = (r.RX, r.DECODER, r.ITM, r.DTO);
+    if let Ok(byte) = rx.read() {
+        match decoder.push(byte) {
+        | Some(Ok(res)) => {
             match res {
             | frame::Inbound::RxPacket { ref data, .. } => {
                 let x = BE::read_i64(&data[1..]); // Assume Data::PollCmd thunderdome packet
@@ -144,7 +152,10 @@ fn receive(_t: &mut Threshold, r: USART2_EXTI26::Resources) {
             | _ => ()
             }
             iprintln!(&mut itm.stim[0], "Recived frame: {:?}", res)
-        }).unwrap();
+        },
+        | Some(Err(e)) => iprintln!(&mut itm.stim[0], "Dropped frame: {:?}", e),
+        | None => ()
+        }
     }
     rx.clear_overrun_error();
 }
@@ -0,0 +1,525 @@
+//! Typed Zigbee AT commands, e.g. `get(Sh)` gives a `u32`.
+//! Numbers are big-endian on the wire, with a width that depends on the command.
+
+use core::str;
+use frame;
+
+#[derive(Debug, PartialEq)]
+pub enum ValueError {
+    /// The value takes at most `max` bytes, but `got` were received
+    Length { max: usize, got: usize },
+    /// Out of range for the command
+    Unknown(u8),
+    InvalidString,
+}
+
+/// Longest parameter value, NI's 20 characters
+pub const MAX_PARAM: usize = 20;
+
+pub type Param = [u8; MAX_PARAM];
+
+/// Parameter or response value, as carried by AT command frames
+pub trait Decode: Sized {
+    fn decode(data: &[u8]) -> Result<Self, ValueError>;
+}
+
+pub trait Encode {
+    fn encode<'p>(&self, param: &'p mut Param) -> &'p [u8];
+}
+
+pub trait Command {
+    const AT: [u8; 2];
+    type Value;
+}
+
+/// Commands that can be queried
+pub trait Get: Command {
+    fn decode(data: &[u8]) -> Result<Self::Value, ValueError>;
+}
+
+/// Commands that take a value, or `()` for the ones that just execute
+pub trait Set: Command {
+    fn encode<'p>(value: &Self::Value, param: &'p mut Param) -> &'p [u8];
+}
+
+/// AT command frame querying `C`
+pub fn query<C: Get>(_: C, frame_id: u8) -> frame::Outbound<'static> {
+    frame::Outbound::AtCommand {
+        frame_id,
+        at_cmd: C::AT,
+        params: &[],
+    }
+}
+
+/// AT command frame setting `C` to `value`, which is encoded into `param`
+pub fn set<'p, C: Set>(
+    _: C,
+    frame_id: u8,
+    value: &C::Value,
+    param: &'p mut Param,
+) -> frame::Outbound<'p> {
+    frame::Outbound::AtCommand {
+        frame_id,
+        at_cmd: C::AT,
+        params: C::encode(value, param),
+    }
+}
+
+macro_rules! uint {
+    ($t:ty, $width:expr) => {
+        impl Decode for $t {
+            /// Leading zero bytes may be left out
+            fn decode(data: &[u8]) -> Result<$t, ValueError> {
+                if data.is_empty() || data.len() > $width {
+                    return Err(ValueError::Length {
+                        max: $width,
+                        got: data.len(),
+                    });
+                }
+                Ok(data.iter().fold(0, |acc: u64, b| acc << 8 | *b as u64) as $t)
+            }
+        }
+
+        impl Encode for $t {
+            fn encode<'p>(&self, param: &'p mut Param) -> &'p [u8] {
+                for (i, b) in param[..$width].iter_mut().enumerate() {
+                    *b = (*self >> (8 * ($width - 1 - i))) as u8;
+                }
+                &param[..$width]
+            }
+        }
+    };
+}
+
+uint!(u8, 1);
+uint!(u16, 2);
+uint!(u32, 4);
+uint!(u64, 8);
+
+impl Decode for i16 {
+    /// Two's complement, leading zero bytes may be left out
+    fn decode(data: &[u8]) -> Result<i16, ValueError> {
+        u16::decode(data).map(|value| value as i16)
+    }
+}
+
+impl Decode for bool {
+    fn decode(data: &[u8]) -> Result<bool, ValueError> {
+        match u8::decode(data)? {
+            | 0 => Ok(false),
+            | 1 => Ok(true),
+            | value => Err(ValueError::Unknown(value)),
+        }
+    }
+}
+
+impl Encode for bool {
+    fn encode<'p>(&self, param: &'p mut Param) -> &'p [u8] {
+        (*self as u8).encode(param)
+    }
+}
+
+impl Decode for () {
+    fn decode(_: &[u8]) -> Result<(), ValueError> {
+        Ok(())
+    }
+}
+
+impl Encode for () {
+    fn encode<'p>(&self, param: &'p mut Param) -> &'p [u8] {
+        &param[..0]
+    }
+}
+
+/// Text value, like the node identifier
+#[derive(Clone, Copy)]
+pub struct Text {
+    data: Param,
+    len: usize,
+}
+
+impl Text {
+    /// `None` if `text` is longer than `MAX_PARAM` bytes
+    pub fn new(text: &str) -> Option<Text> {
+        let mut data = [0; MAX_PARAM];
+        data.get_mut(..text.len())?.copy_from_slice(text.as_bytes());
+        Some(Text {
+            data,
+            len: text.len(),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever built from valid UTF-8
+        str::from_utf8(&self.data[..self.len]).unwrap_or("")
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Text) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl ::core::fmt::Debug for Text {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        ::core::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Decode for Text {
+    fn decode(data: &[u8]) -> Result<Text, ValueError> {
+        let text = str::from_utf8(data).or(Err(ValueError::InvalidString))?;
+        Text::new(text).ok_or(ValueError::Length {
+            max: MAX_PARAM,
+            got: data.len(),
+        })
+    }
+}
+
+impl Encode for Text {
+    fn encode<'p>(&self, param: &'p mut Param) -> &'p [u8] {
+        param[..self.len].copy_from_slice(&self.data[..self.len]);
+        &param[..self.len]
+    }
+}
+
+/// Association indication, read with `AI`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Association {
+    /// Formed or joined a network
+    Joined,
+    ScanFoundNoPan,
+    /// No PAN matches `SC` and `ID`
+    ScanFoundNoValidPan,
+    /// Found coordinators or routers, but none allows joining
+    JoiningNotAllowed,
+    NoJoinableBeacons,
+    /// The node shouldn't be trying to join at this time
+    UnexpectedState,
+    JoinFailed,
+    CoordinatorStartFailed,
+    CheckingForCoordinator,
+    LeaveFailed,
+    /// The device we tried to join didn't respond
+    JoinNoResponse,
+    /// The network key was received unsecured
+    SecureJoinUnsecuredKey,
+    SecureJoinKeyNotReceived,
+    /// The preconfigured link key doesn't match
+    SecureJoinBadLinkKey,
+    /// Routers and end devices, while looking for a network
+    Scanning,
+    Unknown(u8),
+}
+
+impl Decode for Association {
+    fn decode(data: &[u8]) -> Result<Association, ValueError> {
+        Ok(match u8::decode(data)? {
+            | 0x00 => Association::Joined,
+            | 0x21 => Association::ScanFoundNoPan,
+            | 0x22 => Association::ScanFoundNoValidPan,
+            | 0x23 => Association::JoiningNotAllowed,
+            | 0x24 => Association::NoJoinableBeacons,
+            | 0x25 => Association::UnexpectedState,
+            | 0x27 => Association::JoinFailed,
+            | 0x2A => Association::CoordinatorStartFailed,
+            | 0x2B => Association::CheckingForCoordinator,
+            | 0x2C => Association::LeaveFailed,
+            | 0xAB => Association::JoinNoResponse,
+            | 0xAC => Association::SecureJoinUnsecuredKey,
+            | 0xAD => Association::SecureJoinKeyNotReceived,
+            | 0xAF => Association::SecureJoinBadLinkKey,
+            | 0xFF => Association::Scanning,
+            | other => Association::Unknown(other),
+        })
+    }
+}
+
+/// 128-bit encryption key, write-only
+pub type Key = [u8; 16];
+
+impl Encode for Key {
+    fn encode<'p>(&self, param: &'p mut Param) -> &'p [u8] {
+        param[..16].copy_from_slice(self);
+        &param[..16]
+    }
+}
+
+macro_rules! commands {
+    ($($(#[$attr:meta])* $name:ident: $a:expr, $t:expr => $value:ty, $($access:ident)+;)+) => {
+        $(
+            $(#[$attr])*
+            #[derive(Debug, Clone, Copy)]
+            pub struct $name;
+
+            impl Command for $name {
+                const AT: [u8; 2] = [$a, $t];
+                type Value = $value;
+            }
+
+            $(commands!(@$access $name, $value);)+
+        )+
+    };
+    (@get $name:ident, $value:ty) => {
+        impl Get for $name {
+            fn decode(data: &[u8]) -> Result<$value, ValueError> {
+                <$value as Decode>::decode(data)
+            }
+        }
+    };
+    (@set $name:ident, $value:ty) => {
+        impl Set for $name {
+            fn encode<'p>(value: &$value, param: &'p mut Param) -> &'p [u8] {
+                <$value as Encode>::encode(value, param)
+            }
+        }
+    };
+}
+
+// addressing
+commands! {
+    /// Destination address high
+    Dh: b'D', b'H' => u32, get set;
+    /// Destination address low
+    Dl: b'D', b'L' => u32, get set;
+    /// 16-bit network address
+    My: b'M', b'Y' => u16, get;
+    /// 16-bit parent address
+    Mp: b'M', b'P' => u16, get;
+    /// Remaining child capacity
+    Nc: b'N', b'C' => u8, get;
+    /// Serial number high
+    Sh: b'S', b'H' => u32, get;
+    /// Serial number low
+    Sl: b'S', b'L' => u32, get;
+    /// Node identifier
+    Ni: b'N', b'I' => Text, get set;
+    /// Source endpoint
+    Se: b'S', b'E' => u8, get set;
+    /// Destination endpoint
+    De: b'D', b'E' => u8, get set;
+    /// Cluster ID
+    Ci: b'C', b'I' => u16, get set;
+    /// Transmit options
+    To: b'T', b'O' => u8, get set;
+    /// Maximum RF payload bytes
+    Np: b'N', b'P' => u16, get;
+    /// Device type identifier
+    Dd: b'D', b'D' => u32, get set;
+    /// Conflict report
+    Cr: b'C', b'R' => u8, get set;
+}
+
+// networking
+commands! {
+    /// Operating channel
+    Ch: b'C', b'H' => u8, get;
+    /// Configured 64-bit PAN ID, 0 to join any
+    Id: b'I', b'D' => u64, get set;
+    /// Operating 64-bit PAN ID
+    Op: b'O', b'P' => u64, get;
+    /// Maximum unicast hops
+    Nh: b'N', b'H' => u8, get set;
+    /// Broadcast hops
+    Bh: b'B', b'H' => u8, get set;
+    /// Operating 16-bit PAN ID
+    Oi: b'O', b'I' => u16, get;
+    /// Node discovery timeout, in 100 ms
+    Nt: b'N', b'T' => u8, get set;
+    /// Network discovery options
+    No: b'N', b'O' => u8, get set;
+    /// Scan channels bitmask
+    Sc: b'S', b'C' => u16, get set;
+    /// Scan duration exponent
+    Sd: b'S', b'D' => u8, get set;
+    /// Zigbee stack profile
+    Zs: b'Z', b'S' => u8, get set;
+    /// Node join time, in seconds
+    Nj: b'N', b'J' => u8, get set;
+    /// Channel verification
+    Jv: b'J', b'V' => bool, get set;
+    /// Network watchdog timeout, in minutes
+    Nw: b'N', b'W' => u16, get set;
+    /// Join notification
+    Jn: b'J', b'N' => bool, get set;
+    /// Many-to-one route broadcast time, in 10 s
+    Ar: b'A', b'R' => u8, get set;
+    /// Disable joining
+    Dj: b'D', b'J' => bool, get set;
+    /// Initial 16-bit PAN ID
+    Ii: b'I', b'I' => u16, get set;
+    /// Network reset, 0 for this node, 1 for the whole network
+    Nr: b'N', b'R' => u8, set;
+    /// Commissioning pushbutton, e.g. 4 to leave the network
+    Cb: b'C', b'B' => u8, set;
+}
+
+// security
+commands! {
+    /// Encryption enable
+    Ee: b'E', b'E' => bool, get set;
+    /// Encryption options
+    Eo: b'E', b'O' => u8, get set;
+    /// Network encryption key
+    Nk: b'N', b'K' => Key, set;
+    /// Link key
+    Ky: b'K', b'Y' => Key, set;
+}
+
+// RF interfacing
+commands! {
+    /// Power level
+    Pl: b'P', b'L' => u8, get set;
+    /// Power mode, boost on or off
+    Pm: b'P', b'M' => bool, get set;
+    /// RSSI of the last received packet, in -dBm
+    Db: b'D', b'B' => u8, get;
+    /// Peak power, in dBm
+    Pp: b'P', b'P' => u8, get;
+}
+
+// serial interfacing
+commands! {
+    /// API enable, 0 for transparent mode
+    Ap: b'A', b'P' => u8, get set;
+    /// API output options
+    Ao: b'A', b'O' => u8, get set;
+    /// Baud rate, 0-7 for the standard rates or the rate itself
+    Bd: b'B', b'D' => u32, get set;
+    /// Parity
+    Nb: b'N', b'B' => u8, get set;
+    /// Stop bits
+    Sb: b'S', b'B' => u8, get set;
+    /// Packetization timeout, in character times
+    Ro: b'R', b'O' => u8, get set;
+    /// DIO7 configuration
+    D7: b'D', b'7' => u8, get set;
+    /// DIO6 configuration
+    D6: b'D', b'6' => u8, get set;
+}
+
+// I/O
+commands! {
+    /// I/O sample rate, in ms
+    Ir: b'I', b'R' => u16, get set;
+    /// Digital change detection mask
+    Ic: b'I', b'C' => u16, get set;
+    /// DIO10 configuration
+    P0: b'P', b'0' => u8, get set;
+    /// DIO11 configuration
+    P1: b'P', b'1' => u8, get set;
+    /// DIO12 configuration
+    P2: b'P', b'2' => u8, get set;
+    /// AD0/DIO0 configuration
+    D0: b'D', b'0' => u8, get set;
+    /// AD1/DIO1 configuration
+    D1: b'D', b'1' => u8, get set;
+    /// AD2/DIO2 configuration
+    D2: b'D', b'2' => u8, get set;
+    /// AD3/DIO3 configuration
+    D3: b'D', b'3' => u8, get set;
+    /// DIO4 configuration
+    D4: b'D', b'4' => u8, get set;
+    /// DIO5 configuration
+    D5: b'D', b'5' => u8, get set;
+    /// DIO8 configuration
+    D8: b'D', b'8' => u8, get set;
+    /// DIO9 configuration
+    D9: b'D', b'9' => u8, get set;
+    /// Associate LED blink time, in 10 ms
+    Lt: b'L', b'T' => u8, get set;
+    /// Pull-up resistor mask
+    Pr: b'P', b'R' => u16, get set;
+    /// RSSI PWM timer, in 100 ms
+    Rp: b'R', b'P' => u8, get set;
+    /// Supply voltage, in mV
+    PercentV: b'%', b'V' => u16, get;
+    /// Supply voltage threshold, in mV
+    VPlus: b'V', b'+' => u16, get set;
+    /// Module temperature, in degrees Celsius
+    Tp: b'T', b'P' => i16, get;
+}
+
+// sleep
+commands! {
+    /// Sleep mode
+    Sm: b'S', b'M' => u8, get set;
+    /// Number of sleep periods
+    Sn: b'S', b'N' => u16, get set;
+    /// Sleep period, in 10 ms
+    Sp: b'S', b'P' => u16, get set;
+    /// Time before sleep, in ms
+    St: b'S', b'T' => u16, get set;
+    /// Sleep options
+    So: b'S', b'O' => u8, get set;
+    /// Wake host delay, in ms
+    Wh: b'W', b'H' => u16, get set;
+    /// Polling rate, in 10 ms
+    Po: b'P', b'O' => u16, get set;
+    /// Cyclic sleep now
+    Si: b'S', b'I' => (), set;
+}
+
+// diagnostics and execution
+commands! {
+    /// Firmware version
+    Vr: b'V', b'R' => u16, get;
+    /// Hardware version
+    Hv: b'H', b'V' => u16, get;
+    /// Association indication
+    Ai: b'A', b'I' => Association, get;
+    /// Apply changes
+    Ac: b'A', b'C' => (), set;
+    /// Write parameters to non-volatile memory
+    Wr: b'W', b'R' => (), set;
+    /// Restore defaults
+    Re: b'R', b'E' => (), set;
+    /// Software reset
+    Fr: b'F', b'R' => (), set;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_test() {
+        assert_eq!(Sh::decode(&[0x00, 0x13, 0xA2, 0x00]), Ok(0x0013A200));
+        assert_eq!(My::decode(&[0x12]), Ok(0x12));
+        assert_eq!(Ch::decode(&[0x0B, 0x0C]), Err(ValueError::Length { max: 1, got: 2 }));
+        assert_eq!(Ch::decode(&[]), Err(ValueError::Length { max: 1, got: 0 }));
+        assert_eq!(Jn::decode(&[1]), Ok(true));
+        assert_eq!(Jn::decode(&[2]), Err(ValueError::Unknown(2)));
+        assert_eq!(Ni::decode(b"router").unwrap().as_str(), "router");
+        assert_eq!(Ni::decode(&[0xFF]), Err(ValueError::InvalidString));
+        assert_eq!(Ai::decode(&[0x00]), Ok(Association::Joined));
+        assert_eq!(Ai::decode(&[0xFF]), Ok(Association::Scanning));
+        assert_eq!(Ai::decode(&[0x42]), Ok(Association::Unknown(0x42)));
+        assert_eq!(Tp::decode(&[0x19]), Ok(25));
+        assert_eq!(Tp::decode(&[0xFF, 0xFB]), Ok(-5));
+    }
+
+    #[test]
+    fn encode_test() {
+        let mut param = [0; MAX_PARAM];
+        let id = 0x0123456789ABCDEF;
+        match set(Id, 0x01, &id, &mut param) {
+            | frame::Outbound::AtCommand {
+                frame_id,
+                at_cmd,
+                params,
+            } => {
+                assert_eq!(frame_id, 0x01);
+                assert_eq!(at_cmd, [b'I', b'D']);
+                assert_eq!(params, &[0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+            }
+            | _ => unreachable!(),
+        }
+        assert_eq!(Ni::encode(&Text::new("router").unwrap(), &mut param), b"router");
+        assert_eq!(Wr::encode(&(), &mut param), &[]);
+        assert!(Text::new("a node identifier too long").is_none());
+        let key = [0xAA; 16];
+        assert_eq!(Ky::encode(&key, &mut param), &key[..]);
+    }
+}
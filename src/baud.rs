@@ -0,0 +1,296 @@
+//! Baud rate detection and renegotiation, for modules left at an unknown `BD`.
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::serial::Write as BlockingWrite;
+use embedded_hal::serial::Read;
+use embedded_hal::timer::CountDown;
+
+use at;
+use frame::AtCommandStatus;
+use serializer::{ApiMode, DeserializationError};
+use {ApiError, CommandError, XBeeApiUart, XBeeTransparent};
+
+/// Rates selected by `BD` 0 to 7, other values are the rate itself
+pub const RATES: [u32; 8] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200];
+
+/// A UART whose baud rate can be changed on the fly
+pub trait Reconfigure {
+    type Error;
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum BaudError<E> {
+    /// None of the candidate rates got an answer
+    NotDetected,
+    Reconfigure(E),
+    Api(ApiError<E>),
+}
+
+impl<E> From<ApiError<E>> for BaudError<E> {
+    fn from(e: ApiError<E>) -> BaudError<E> {
+        BaudError::Api(e)
+    }
+}
+
+impl<E> From<CommandError<E>> for BaudError<E> {
+    fn from(e: CommandError<E>) -> BaudError<E> {
+        BaudError::Api(ApiError::Command(e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detected {
+    pub baud_rate: u32,
+    /// `None` in transparent mode
+    pub mode: Option<ApiMode>,
+}
+
+/// `BD` value selecting `baud_rate`, the rate itself for non-standard ones from 0x80 up.
+/// `None` for lower ones, which would select a standard rate instead.
+pub fn bd(baud_rate: u32) -> Option<u32> {
+    match RATES.iter().position(|r| *r == baud_rate) {
+        | Some(i) => Some(i as u32),
+        | None if baud_rate >= 0x80 => Some(baud_rate),
+        | None => None,
+    }
+}
+
+/// Probes baud rates with the command sequence and with an `AP` query in either API mode.
+/// Holds what entering command mode takes, see `XBeeTransparent::new`.
+pub struct Autobaud<'d, D: 'd> {
+    delay: &'d mut D,
+    cmd_char: u8,
+    guard_time: u16,
+}
+
+impl<'d, D> Autobaud<'d, D>
+where
+    D: DelayMs<u16>,
+{
+    pub fn new(delay: &'d mut D, cmd_char: u8, guard_time: u16) -> Autobaud<'d, D> {
+        Autobaud {
+            delay,
+            cmd_char,
+            guard_time,
+        }
+    }
+
+    /// Tries each of `candidates` in turn, e.g. `RATES`, and leaves `uart` at the detected one.
+    /// Command mode comes first, so that a module in transparent mode doesn't transmit
+    /// the API frame. `timeout` applies to each reply.
+    pub fn detect<E, U, T>(
+        &mut self,
+        uart: &mut U,
+        candidates: &[u32],
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<Detected, BaudError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E> + Reconfigure<Error = E>,
+        T: CountDown,
+        T::Time: Clone,
+    {
+        for &baud_rate in candidates {
+            uart.set_baud_rate(baud_rate).map_err(BaudError::Reconfigure)?;
+            if self.command_mode(uart, timer, timeout.clone())? {
+                return Ok(Detected { baud_rate, mode: None });
+            }
+            if let Some(mode) = api_ping(uart, timer, timeout.clone())? {
+                return Ok(Detected {
+                    baud_rate,
+                    mode: Some(mode),
+                });
+            }
+        }
+        Err(BaudError::NotDetected)
+    }
+
+    /// Sets `BD` for `baud_rate` on the module found by `detect`, persisting it with `WR`
+    /// if `persist` is set, then switches `uart` and confirms the module answers there.
+    /// A rate `bd` has no value for fails with `InvalidParam` before anything is sent.
+    pub fn switch<E, U, T>(
+        &mut self,
+        uart: &mut U,
+        detected: Detected,
+        baud_rate: u32,
+        persist: bool,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<Detected, BaudError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E> + Reconfigure<Error = E>,
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let value = bd(baud_rate).ok_or(ApiError::AtCommand {
+            at_cmd: [b'B', b'D'],
+            status: AtCommandStatus::InvalidParam,
+        })?;
+        match detected.mode {
+            | Some(mode) => {
+                let mut buffer = [0; 64];
+                let mut xbee = XBeeApiUart::new(&mut *uart, mode, &mut buffer);
+                xbee.queue(at::Bd, &value, &mut |_| {}, timer, timeout.clone())?;
+                xbee.commit(persist, &mut |_| {}, timer, timeout.clone())?;
+            }
+            | None => {
+                let mut xbee = XBeeTransparent::new(
+                    &mut *uart,
+                    &mut *self.delay,
+                    self.cmd_char,
+                    self.guard_time,
+                );
+                xbee.enter_command_mode(timer, timeout.clone())?;
+                let mut param = [0; 8];
+                let mut reply = [0; 8];
+                xbee.at_command(
+                    [b'B', b'D'],
+                    hex(value, &mut param),
+                    &mut reply,
+                    timer,
+                    timeout.clone(),
+                )?;
+                if persist {
+                    xbee.at_command([b'W', b'R'], &[], &mut reply, timer, timeout.clone())?;
+                }
+                // the new rate applies once command mode is left
+                xbee.exit_command_mode(timer, timeout.clone())?;
+            }
+        }
+        self.detect(uart, &[baud_rate], timer, timeout)
+    }
+
+    // true if the module entered command mode, which is then left again
+    fn command_mode<E, U, T>(
+        &mut self,
+        uart: &mut U,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<bool, BaudError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let mut xbee = XBeeTransparent::new(uart, &mut *self.delay, self.cmd_char, self.guard_time);
+        match xbee.enter_command_mode(timer, timeout.clone()) {
+            | Ok(()) => {}
+            // noise at the wrong rate
+            | Err(CommandError::Timeout)
+            | Err(CommandError::Unexpected(_))
+            | Err(CommandError::Error)
+            | Err(CommandError::WouldOverflow) => return Ok(false),
+            | Err(CommandError::Other(e)) => return Err(CommandError::Other(e).into()),
+        }
+        xbee.exit_command_mode(timer, timeout)?;
+        Ok(true)
+    }
+}
+
+fn api_ping<E, U, T>(
+    uart: &mut U,
+    timer: &mut T,
+    timeout: T::Time,
+) -> Result<Option<ApiMode>, BaudError<E>>
+where
+    U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+    T: CountDown,
+    T::Time: Clone,
+{
+    let mut buffer = [0; 64];
+    let mut xbee = XBeeApiUart::new(uart, ApiMode::Unescaped, &mut buffer);
+    match xbee.detect_mode(&mut |_| {}, timer, timeout) {
+        | Ok(mode) => Ok(Some(mode)),
+        // noise at the wrong rate
+        | Err(ApiError::Timeout)
+        | Err(ApiError::Deserialization(DeserializationError::Parse(_)))
+        | Err(ApiError::Deserialization(DeserializationError::BadChecksum(_)))
+        | Err(ApiError::Deserialization(DeserializationError::Incomplete)) => Ok(None),
+        | Err(e) => Err(e.into()),
+    }
+}
+
+// as text for command mode
+fn hex(value: u32, buffer: &mut [u8; 8]) -> &[u8] {
+    let digits = b"0123456789ABCDEF";
+    let len = (8 - value.leading_zeros() as usize / 4).max(1);
+    for (i, b) in buffer[..len].iter_mut().enumerate() {
+        *b = digits[(value >> (4 * (len - 1 - i)) & 0xF) as usize];
+    }
+    &buffer[..len]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mock::{Delay, Module, PollTimer};
+    use serializer::SerializationError;
+
+    // never answers, and fails to write
+    struct Dead;
+
+    impl Read<u8> for Dead {
+        type Error = ();
+
+        fn read(&mut self) -> ::nb::Result<u8, ()> {
+            Err(::nb::Error::WouldBlock)
+        }
+    }
+
+    impl BlockingWrite<u8> for Dead {
+        type Error = ();
+
+        fn bwrite_all(&mut self, _: &[u8]) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn bflush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bd_test() {
+        assert_eq!(bd(9600), Some(3));
+        assert_eq!(bd(115200), Some(7));
+        assert_eq!(bd(250000), Some(250000));
+        assert_eq!(bd(3), None);
+        let mut buffer = [0; 8];
+        assert_eq!(hex(0, &mut buffer), b"0");
+        assert_eq!(hex(7, &mut buffer), b"7");
+        assert_eq!(hex(250000, &mut buffer), b"3D090");
+    }
+
+    #[test]
+    fn detect_switch_test() {
+        let mut module = Module::new(&[(*b"AP", 1), (*b"BD", 5)]);
+        let mut delay = Delay;
+        let mut timer = PollTimer { remaining: 0 };
+        let mut autobaud = Autobaud::new(&mut delay, b'+', 1000);
+        let detected = autobaud.detect(&mut module, &RATES, &mut timer, 10).unwrap();
+        assert_eq!(
+            detected,
+            Detected {
+                baud_rate: 38400,
+                mode: Some(ApiMode::Unescaped),
+            }
+        );
+        let switched = autobaud
+            .switch(&mut module, detected, 115200, true, &mut timer, 10)
+            .unwrap();
+        assert_eq!(switched.baud_rate, 115200);
+        assert_eq!(module.param(*b"BD"), Some(7));
+        assert_eq!(module.baud_rate, 115200);
+    }
+
+    #[test]
+    fn dead_uart_test() {
+        let mut timer = PollTimer { remaining: 0 };
+        match api_ping(&mut Dead, &mut timer, 10) {
+            | Err(BaudError::Api(ApiError::Serialization(SerializationError::Other(())))) => {}
+            | res => panic!("{:?}", res),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! 64-bit to 16-bit address cache, so that transmissions don't need address discovery.
+
+use heapless::{consts, Vec};
+
+use frame::{self, Address, AtCommandStatus, NodeAddress, NodeInfo, TxStatus, MAC};
+use XBeeApi;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    mac: MAC,
+    addr: Address,
+    used: u32,
+}
+
+/// Learns the 16-bit addresses of nodes from the frames passed to `update`,
+/// and fills them in for transmissions to their 64-bit address with `fill`.
+/// Holds 16 nodes, the least recently used one is forgotten to make room.
+pub struct AddressCache {
+    entries: Vec<Entry, consts::U16>,
+    // destinations of transmissions waiting for their status, by frame ID, with when they were sent
+    pending: Vec<(u8, MAC, u32), consts::U8>,
+    clock: u32,
+}
+
+impl Default for AddressCache {
+    fn default() -> AddressCache {
+        AddressCache::new()
+    }
+}
+
+impl AddressCache {
+    pub fn new() -> AddressCache {
+        AddressCache {
+            entries: Vec::new(),
+            pending: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&mut self, mac: &MAC) -> Option<Address> {
+        let used = self.tick();
+        self.entries.iter_mut().find(|e| e.mac == *mac).map(|e| {
+            e.used = used;
+            e.addr
+        })
+    }
+
+    /// Learns a mapping, e.g. of a node found by `discovery::find`.
+    /// Broadcast and unknown addresses are ignored.
+    pub fn insert(&mut self, mac: MAC, addr: Address) {
+        if !is_unicast(&mac) || addr == Address::UNKNOWN || addr.high == 0xFF && addr.low >= 0xFC {
+            return;
+        }
+        let used = self.tick();
+        if let Some(e) = self.entries.iter_mut().find(|e| e.mac == mac) {
+            e.addr = addr;
+            e.used = used;
+            return;
+        }
+        if self.entries.len() == self.entries.capacity() {
+            let lru = self.entries
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, e)| e.used)
+                .map(|(i, _)| i);
+            if let Some(i) = lru {
+                self.entries.swap_remove(i);
+            }
+        }
+        let _ = self.entries.push(Entry { mac, addr, used });
+    }
+
+    /// Learns a node found by `discovery::discover`
+    pub fn insert_node(&mut self, node: &NodeInfo) {
+        self.insert(node.mac, node.addr)
+    }
+
+    pub fn remove(&mut self, mac: &MAC) {
+        if let Some(i) = self.entries.iter().position(|e| e.mac == *mac) {
+            self.entries.swap_remove(i);
+        }
+    }
+
+    /// Learns from the source of received packets, IO samples, remote AT command responses
+    /// and node identification, from `ND` and `DN` responses, and from the status of
+    /// transmissions `fill` has seen.
+    /// A status of `AddressNotFound` or `RouteNotFound` forgets the destination.
+    pub fn update(&mut self, frame: &frame::Inbound) {
+        match *frame {
+            | frame::Inbound::RxPacket {
+                ref source_mac,
+                ref source_addr,
+                ..
+            }
+            | frame::Inbound::ExplicitRxPacket {
+                ref source_mac,
+                ref source_addr,
+                ..
+            }
+            | frame::Inbound::IoSample {
+                ref source_mac,
+                ref source_addr,
+                ..
+            }
+            | frame::Inbound::RemoteAtCommandResponse {
+                ref source_mac,
+                ref source_addr,
+                ..
+            } => self.insert(*source_mac, *source_addr),
+            | frame::Inbound::NodeIdentification { ref node, .. } => {
+                self.insert(node.remote_mac, node.remote_addr)
+            }
+            | frame::Inbound::AtCommandResponse {
+                at_cmd,
+                status: AtCommandStatus::Ok,
+                data,
+                ..
+            } => match &at_cmd {
+                | b"ND" => if let Ok(node) = NodeInfo::parse(data) {
+                    self.insert_node(&node)
+                },
+                | b"DN" => if let Ok(node) = NodeAddress::parse(data) {
+                    self.insert(node.mac, node.addr)
+                },
+                | _ => {}
+            },
+            | frame::Inbound::TransmitStatus {
+                frame_id,
+                ref dest_addr,
+                ref status,
+                ..
+            } => {
+                if let Some(i) = self.pending.iter().position(|p| p.0 == frame_id) {
+                    let (_, mac, _) = self.pending.swap_remove(i);
+                    match *status {
+                        | TxStatus::Success => self.insert(mac, *dest_addr),
+                        | TxStatus::AddressNotFound | TxStatus::RouteNotFound => self.remove(&mac),
+                        | _ => {}
+                    }
+                }
+            }
+            | _ => {}
+        }
+    }
+
+    /// Fills in the 16-bit address of a `TxRequest` or `ExplicitTxRequest` if it's
+    /// `Address::UNKNOWN` and the 64-bit one is known. With a frame ID, the destination
+    /// is remembered until its `TransmitStatus` is passed to `update`.
+    pub fn fill(&mut self, frame: &mut frame::Outbound) {
+        match *frame {
+            | frame::Outbound::TxRequest {
+                frame_id,
+                ref dest_mac,
+                ref mut dest_addr,
+                ..
+            }
+            | frame::Outbound::ExplicitTxRequest {
+                frame_id,
+                ref dest_mac,
+                ref mut dest_addr,
+                ..
+            } => {
+                if *dest_addr == Address::UNKNOWN {
+                    if let Some(addr) = self.get(dest_mac) {
+                        *dest_addr = addr;
+                    }
+                }
+                if frame_id != 0 && is_unicast(dest_mac) {
+                    self.track(frame_id, *dest_mac);
+                }
+            }
+            | _ => {}
+        }
+    }
+
+    /// Sends `frame` once it's filled in
+    pub fn send<X: XBeeApi>(
+        &mut self,
+        xbee: &mut X,
+        frame: &mut frame::Outbound,
+    ) -> Result<(), X::Error> {
+        self.fill(frame);
+        xbee.send(frame)
+    }
+
+    fn track(&mut self, frame_id: u8, mac: MAC) {
+        if let Some(i) = self.pending.iter().position(|p| p.0 == frame_id) {
+            self.pending.swap_remove(i);
+        }
+        // a status that never came shouldn't block the others
+        if self.pending.len() == self.pending.capacity() {
+            let oldest = self.pending
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, p)| p.2)
+                .map(|(i, _)| i);
+            if let Some(i) = oldest {
+                self.pending.swap_remove(i);
+            }
+        }
+        let sent = self.tick();
+        let _ = self.pending.push((frame_id, mac, sent));
+    }
+
+    fn tick(&mut self) -> u32 {
+        self.clock = self.clock.wrapping_add(1);
+        self.clock
+    }
+}
+
+fn is_unicast(mac: &MAC) -> bool {
+    *mac != MAC::UNKNOWN && *mac != MAC::BROADCAST
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use frame::{DiscoStatus, RxOptions, TxOptions};
+
+    const NODE: MAC = MAC {
+        high: 0x0013A200,
+        low: 0x40522BAA,
+    };
+
+    fn tx_request(frame_id: u8) -> frame::Outbound<'static> {
+        frame::Outbound::TxRequest {
+            frame_id,
+            dest_mac: NODE,
+            dest_addr: Address::UNKNOWN,
+            bc_radius: 0,
+            options: TxOptions::empty(),
+            data: &[0x01],
+        }
+    }
+
+    fn transmit_status(
+        frame_id: u8,
+        dest_addr: Address,
+        status: TxStatus,
+    ) -> frame::Inbound<'static> {
+        frame::Inbound::TransmitStatus {
+            frame_id,
+            dest_addr,
+            txr_count: 0,
+            status,
+            disco_status: DiscoStatus::NoDiscoveryOverhead,
+        }
+    }
+
+    fn dest_addr(frame: &frame::Outbound) -> Address {
+        match *frame {
+            | frame::Outbound::TxRequest { dest_addr, .. } => dest_addr,
+            | _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn learn_fill_test() {
+        let mut cache = AddressCache::new();
+        let mut frame = tx_request(1);
+        cache.fill(&mut frame);
+        assert_eq!(dest_addr(&frame), Address::UNKNOWN);
+
+        let addr = Address { high: 0x7D, low: 0x84 };
+        cache.update(&transmit_status(1, addr, TxStatus::Success));
+        let mut frame = tx_request(2);
+        cache.fill(&mut frame);
+        assert_eq!(dest_addr(&frame), addr);
+
+        cache.update(&transmit_status(2, addr, TxStatus::RouteNotFound));
+        assert_eq!(cache.get(&NODE), None);
+
+        cache.update(&frame::Inbound::RxPacket {
+            source_mac: NODE,
+            source_addr: Address { high: 0x12, low: 0x34 },
+            options: RxOptions::empty(),
+            data: &[],
+        });
+        let mut frame = tx_request(0);
+        cache.fill(&mut frame);
+        assert_eq!(dest_addr(&frame), Address { high: 0x12, low: 0x34 });
+
+        cache.insert(MAC::BROADCAST, Address::COORDINATOR);
+        cache.insert(NODE, Address::UNKNOWN);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn learn_nodes_test() {
+        let mut cache = AddressCache::new();
+        let data = [
+            0x7D, 0x84, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x4C, 0x31, 0x00, 0xFF,
+            0xFE, 0x01, 0x00, 0xC1, 0x05, 0x10, 0x1E,
+        ];
+        cache.update(&frame::Inbound::AtCommandResponse {
+            frame_id: 1,
+            at_cmd: *b"ND",
+            status: AtCommandStatus::Ok,
+            data: &data,
+        });
+        assert_eq!(cache.get(&NODE), Some(Address { high: 0x7D, low: 0x84 }));
+
+        cache.update(&frame::Inbound::AtCommandResponse {
+            frame_id: 2,
+            at_cmd: *b"DN",
+            status: AtCommandStatus::Ok,
+            data: &[0x12, 0x34, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA],
+        });
+        assert_eq!(cache.get(&NODE), Some(Address { high: 0x12, low: 0x34 }));
+
+        let node = NodeInfo::parse(&data).unwrap();
+        cache.insert_node(&node);
+        assert_eq!(cache.get(&NODE), Some(Address { high: 0x7D, low: 0x84 }));
+    }
+
+    #[test]
+    fn pending_test() {
+        let mut cache = AddressCache::new();
+        for frame_id in 1..11 {
+            cache.fill(&mut tx_request(frame_id));
+        }
+        let addr = Address { high: 0x7D, low: 0x84 };
+        // the two oldest were dropped
+        cache.update(&transmit_status(2, addr, TxStatus::Success));
+        assert_eq!(cache.get(&NODE), None);
+        cache.update(&transmit_status(8, addr, TxStatus::Success));
+        assert_eq!(cache.get(&NODE), Some(addr));
+    }
+
+    #[test]
+    fn evict_test() {
+        let mut cache = AddressCache::new();
+        for i in 0..16 {
+            cache.insert(MAC { high: 0, low: i + 1 }, Address { high: 0, low: i as u8 + 1 });
+        }
+        cache.get(&MAC { high: 0, low: 1 });
+        cache.insert(NODE, Address { high: 0x7D, low: 0x84 });
+        assert_eq!(cache.len(), 16);
+        assert_eq!(cache.get(&MAC { high: 0, low: 1 }), Some(Address { high: 0, low: 1 }));
+        assert_eq!(cache.get(&MAC { high: 0, low: 2 }), None);
+        assert_eq!(cache.get(&NODE), Some(Address { high: 0x7D, low: 0x84 }));
+    }
+}
@@ -0,0 +1,204 @@
+//! Network discovery with `ND`, and node lookup with `DN`.
+
+use embedded_hal::blocking::serial::Write as BlockingWrite;
+use embedded_hal::serial::Read;
+use embedded_hal::timer::CountDown;
+
+use frame::{self, NodeAddress, NodeInfo};
+use serializer::DeserializationError;
+use {ApiError, XBeeApi, XBeeApiUart};
+
+const ND: [u8; 2] = [b'N', b'D'];
+const DN: [u8; 2] = [b'D', b'N'];
+
+/// Discovers nodes with `ND`, only the one named `node_id` unless it's empty.
+/// Each node answers with its own response, all of them passed to `cont`,
+/// until `timeout` expires, which should be longer than `NT`.
+/// Returns the number of nodes found. Unrelated frames are passed to `handler`,
+/// and so are responses that don't parse, e.g. from a device type unknown here.
+/// `cont` can hand the nodes to `AddressCache::insert_node` to skip address discovery later.
+pub fn discover<'a, E, U, C, H, T>(
+    xbee: &mut XBeeApiUart<'a, U>,
+    node_id: &str,
+    cont: &mut C,
+    handler: &mut H,
+    timer: &mut T,
+    timeout: T::Time,
+) -> Result<usize, ApiError<E>>
+where
+    U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+    C: FnMut(NodeInfo),
+    H: FnMut(frame::Inbound),
+    T: CountDown,
+{
+    let id = xbee.frame_ids().allocate().ok_or(ApiError::NoFrameId)?;
+    let res = collect(xbee, id, node_id, cont, handler, timer, timeout);
+    // the first response released it already, but not an unanswered one
+    xbee.frame_ids().release(id);
+    res
+}
+
+fn collect<'a, E, U, C, H, T>(
+    xbee: &mut XBeeApiUart<'a, U>,
+    id: u8,
+    node_id: &str,
+    cont: &mut C,
+    handler: &mut H,
+    timer: &mut T,
+    timeout: T::Time,
+) -> Result<usize, ApiError<E>>
+where
+    U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+    C: FnMut(NodeInfo),
+    H: FnMut(frame::Inbound),
+    T: CountDown,
+{
+    xbee.send(&frame::Outbound::AtCommand {
+        frame_id: id,
+        at_cmd: ND,
+        params: node_id.as_bytes(),
+    })?;
+    timer.start(timeout);
+    let mut found = 0;
+    loop {
+        match xbee.poll_frame() {
+            | Ok(Some(frame::Inbound::AtCommandResponse {
+                frame_id,
+                at_cmd,
+                status,
+                data,
+            })) if frame_id == id && at_cmd == ND => {
+                if status != frame::AtCommandStatus::Ok {
+                    return Err(ApiError::AtCommand { at_cmd, status });
+                }
+                // some firmware ends with an empty response
+                if data.is_empty() {
+                    return Ok(found);
+                }
+                match NodeInfo::parse(data) {
+                    | Ok(node) => {
+                        cont(node);
+                        found += 1;
+                    }
+                    | Err(_) => handler(frame::Inbound::AtCommandResponse {
+                        frame_id,
+                        at_cmd,
+                        status,
+                        data,
+                    }),
+                }
+            }
+            | Ok(Some(frame)) => handler(frame),
+            | Ok(None) => {}
+            | Err(::nb::Error::WouldBlock) => if timer.wait().is_ok() {
+                return Ok(found);
+            },
+            | Err(::nb::Error::Other(e)) => return Err(e),
+        }
+    }
+}
+
+/// Looks up the node named `node_id` with `DN`, which also makes it the destination (`DH`/`DL`).
+/// Fails with `ApiError::AtCommand` if no node answers.
+pub fn find<'a, E, U, H, T>(
+    xbee: &mut XBeeApiUart<'a, U>,
+    node_id: &str,
+    handler: &mut H,
+    timer: &mut T,
+    timeout: T::Time,
+) -> Result<NodeAddress, ApiError<E>>
+where
+    U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+    H: FnMut(frame::Inbound),
+    T: CountDown,
+{
+    let frame = |frame_id| frame::Outbound::AtCommand {
+        frame_id,
+        at_cmd: DN,
+        params: node_id.as_bytes(),
+    };
+    match xbee.send_and_wait(frame, handler, timer, timeout)? {
+        | frame::Inbound::AtCommandResponse {
+            status: frame::AtCommandStatus::Ok,
+            data,
+            ..
+        } => Ok(NodeAddress::parse(data).map_err(DeserializationError::Parse)?),
+        | frame::Inbound::AtCommandResponse { status, .. } => {
+            Err(ApiError::AtCommand { at_cmd: DN, status })
+        }
+        | _ => Err(ApiError::UnexpectedResponse),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cache::AddressCache;
+    use frame::{Address, DeviceType, MAC};
+    use mock::{Module, PollTimer};
+    use serializer::ApiMode;
+
+    const NODES: &[&[u8]] = &[
+        &[
+            0x7D, 0x84, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x4C, 0x31, 0x00, 0xFF,
+            0xFE, 0x01, 0x00, 0xC1, 0x05, 0x10, 0x1E,
+        ],
+        // cut short
+        &[0x12, 0x34, 0x00, 0x13],
+        &[
+            0x12, 0x34, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xBB, 0x45, 0x44, 0x00, 0x7D,
+            0x84, 0x02, 0x00, 0xC1, 0x05, 0x10, 0x1E,
+        ],
+    ];
+
+    #[test]
+    fn discover_test() {
+        let mut module = Module::new(&[]);
+        module.script(ND, NODES);
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+        let mut timer = PollTimer { remaining: 0 };
+        let expected = [
+            (Address { high: 0x7D, low: 0x84 }, "L1", DeviceType::Router),
+            (Address { high: 0x12, low: 0x34 }, "ED", DeviceType::EndDevice),
+        ];
+        let mut n = 0;
+        let mut cache = AddressCache::new();
+        let mut unparsed = 0;
+        let found = discover(
+            &mut xbee,
+            "",
+            &mut |node| {
+                cache.insert_node(&node);
+                assert_eq!((node.addr, node.node_id, node.device_type), expected[n]);
+                n += 1;
+            },
+            &mut |_| unparsed += 1,
+            &mut timer,
+            10,
+        ).unwrap();
+        assert_eq!(found, 2);
+        assert_eq!(unparsed, 1);
+        assert_eq!(n, 2);
+        assert_eq!(cache.len(), 2);
+        assert!(!xbee.frame_ids().is_in_flight(1));
+    }
+
+    #[test]
+    fn find_test() {
+        let mut module = Module::new(&[]);
+        module.script(DN, &[&[0x7D, 0x84, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA]]);
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+        let mut timer = PollTimer { remaining: 0 };
+        let node = find(&mut xbee, "L1", &mut |_| {}, &mut timer, 10).unwrap();
+        assert_eq!(node.addr, Address { high: 0x7D, low: 0x84 });
+        assert_eq!(
+            node.mac,
+            MAC {
+                high: 0x0013A200,
+                low: 0x40522BAA,
+            }
+        );
+    }
+}
@@ -1,6 +1,75 @@
 use core::iter::ExactSizeIterator;
+use core::str;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The frame ended after `got` bytes, but at least `needed` were required
+    Truncated { needed: usize, got: usize },
+    UnknownStatus { field: &'static str, value: u8 },
+    InvalidString { field: &'static str },
+    UnknownFrameType(u8),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, offset: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ParseError> {
+        let end = self.offset + n;
+        if end > self.data.len() {
+            return Err(ParseError::Truncated {
+                needed: end,
+                got: self.data.len(),
+            });
+        }
+        let res = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(res)
+    }
+
+    fn byte(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, ParseError> {
+        let b = self.take(2)?;
+        Ok(((b[0] as u16) << 8) | (b[1] as u16))
+    }
+
+    fn status<T, F: FnOnce(u8) -> Result<T, u8>>(
+        &mut self,
+        field: &'static str,
+        from: F,
+    ) -> Result<T, ParseError> {
+        from(self.byte()?).map_err(|value| ParseError::UnknownStatus { field, value })
+    }
+
+    /// Null-terminated string
+    fn string(&mut self, field: &'static str) -> Result<&'a str, ParseError> {
+        let rest = &self.data[self.offset..];
+        let end = rest.iter().position(|b| *b == 0).ok_or(ParseError::Truncated {
+            needed: self.data.len() + 1,
+            got: self.data.len(),
+        })?;
+        let res = str::from_utf8(&rest[..end]).or(Err(ParseError::InvalidString { field }))?;
+        self.offset += end + 1;
+        Ok(res)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let res = &self.data[self.offset..];
+        self.offset = self.data.len();
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Address {
     pub high: u8,
     pub low: u8,
@@ -13,11 +82,12 @@ impl Address {
         low: 0xFE,
     };
 
-    fn from<'a>(iter: &mut Iterator<Item = &'a u8>) -> Address {
-        Address {
-            high: *iter.next().unwrap(),
-            low: *iter.next().unwrap(),
-        }
+    fn from(reader: &mut Reader) -> Result<Address, ParseError> {
+        let b = reader.take(2)?;
+        Ok(Address {
+            high: b[0],
+            low: b[1],
+        })
     }
 
     // fn from_word(word: u16) -> Address {
@@ -28,7 +98,7 @@ impl Address {
     // }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct MAC {
     pub high: u32,
     pub low: u32,
@@ -40,18 +110,20 @@ impl MAC {
         high: 0x0000,
         low: 0xFFFF,
     };
+    /// Used in place of the 64-bit address to target a node by its 16-bit address
+    pub const UNKNOWN: MAC = MAC {
+        high: 0xFFFFFFFF,
+        low: 0xFFFFFFFF,
+    };
 
-    fn from<'a>(iter: &mut Iterator<Item = &'a u8>) -> MAC {
-        MAC {
-            high: ((*iter.next().unwrap() as u32) << 24)
-                | ((*iter.next().unwrap() as u32) << 16)
-                | ((*iter.next().unwrap() as u32) << 8)
-                | (*iter.next().unwrap() as u32),
-            low: ((*iter.next().unwrap() as u32) << 24)
-                | ((*iter.next().unwrap() as u32) << 16)
-                | ((*iter.next().unwrap() as u32) << 8)
-                | (*iter.next().unwrap() as u32),
-        }
+    fn from(reader: &mut Reader) -> Result<MAC, ParseError> {
+        let b = reader.take(8)?;
+        Ok(MAC {
+            high: ((b[0] as u32) << 24) | ((b[1] as u32) << 16) | ((b[2] as u32) << 8)
+                | (b[3] as u32),
+            low: ((b[4] as u32) << 24) | ((b[5] as u32) << 16) | ((b[6] as u32) << 8)
+                | (b[7] as u32),
+        })
     }
 
     fn at(&self, i: usize) -> Result<u8, ()> {
@@ -77,6 +149,15 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct RemoteAtOptions: u8 {
+        const DISABLE_RETRIES = 0x01;
+        const APPLY_CHANGES = 0x02;
+        const ENABLE_ENCRYPTION = 0x20;
+        const EXTENDED_TIMEOUT = 0x40;
+    }
+}
+
 bitflags! {
     pub struct RxOptions: u8 {
         const PACKET_ACKNOWLEDGED = 0x01;
@@ -85,18 +166,14 @@ bitflags! {
     }
 }
 
-/// bitfield
-/// [0..2] reserved
-/// [3..6] analog
-/// [7..15] digital
-// TODO: test if the reserved bits are on the top or bottom and what order
 bitflags! {
+    /// Digital channel mask of an IO sample, also used for the sampled pin levels.
+    /// Checked against 0x92 captures: DIO0 is the least significant bit,
+    /// bits 8 and 9 are not used, DIO10..DIO12 are bits 10..12.
     pub struct ChannelIndicator: u16 {
-        const A3 = 0b0001000000000000;
-        const A2 = 0b0000100000000000;
-        const A1 = 0b0000010000000000;
-        const A0 = 0b0000001000000000;
-        const D8 = 0b0000000100000000;
+        const D12 = 0b0001000000000000;
+        const D11 = 0b0000100000000000;
+        const D10 = 0b0000010000000000;
         const D7 = 0b0000000010000000;
         const D6 = 0b0000000001000000;
         const D5 = 0b0000000000100000;
@@ -110,7 +187,7 @@ bitflags! {
 
 impl ChannelIndicator {
     fn contains_digital(&self) -> bool {
-        self.contains(
+        self.intersects(
             ChannelIndicator::D0
                 | ChannelIndicator::D1
                 | ChannelIndicator::D2
@@ -119,11 +196,106 @@ impl ChannelIndicator {
                 | ChannelIndicator::D5
                 | ChannelIndicator::D6
                 | ChannelIndicator::D7
-                | ChannelIndicator::D8,
+                | ChannelIndicator::D10
+                | ChannelIndicator::D11
+                | ChannelIndicator::D12,
         )
     }
 }
 
+bitflags! {
+    /// Analog channel mask of an IO sample, samples follow in the order of the bits
+    pub struct AnalogIndicator: u8 {
+        const SUPPLY_VOLTAGE = 0b10000000;
+        const A3 = 0b00001000;
+        const A2 = 0b00000100;
+        const A1 = 0b00000010;
+        const A0 = 0b00000001;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IoSample {
+    pub digital_mask: ChannelIndicator,
+    pub analog_mask: AnalogIndicator,
+    pub digital: ChannelIndicator,
+    /// Raw readings of A0..A3 (10-bit) and the supply voltage, valid if set in `analog_mask`
+    pub analog: [u16; 5],
+}
+
+impl IoSample {
+    /// ADC reference voltage, a reading of 0x3FF
+    pub const VREF_MV: u32 = 1200;
+    /// The supply voltage reading is in steps of 1200/1024 mV, not limited to 10 bits
+    pub const SUPPLY_STEPS: u32 = 1024;
+
+    fn from(reader: &mut Reader) -> Result<IoSample, ParseError> {
+        reader.byte()?; // number of sample sets, always 1
+        let digital_mask = ChannelIndicator::from_bits_truncate(reader.word()?);
+        let analog_mask = AnalogIndicator::from_bits_truncate(reader.byte()?);
+        let digital = if digital_mask.contains_digital() {
+            ChannelIndicator::from_bits_truncate(reader.word()?)
+        } else {
+            ChannelIndicator::empty()
+        };
+        let mut analog = [0; 5];
+        for (i, channel) in [
+            AnalogIndicator::A0,
+            AnalogIndicator::A1,
+            AnalogIndicator::A2,
+            AnalogIndicator::A3,
+            AnalogIndicator::SUPPLY_VOLTAGE,
+        ].iter()
+            .enumerate()
+        {
+            if analog_mask.contains(*channel) {
+                analog[i] = reader.word()?;
+            }
+        }
+        Ok(IoSample {
+            digital_mask,
+            analog_mask,
+            digital,
+            analog,
+        })
+    }
+
+    /// Level of a single digital pin, `None` if it wasn't sampled
+    pub fn digital(&self, pin: ChannelIndicator) -> Option<bool> {
+        if self.digital_mask.contains(pin) {
+            Some(self.digital.contains(pin))
+        } else {
+            None
+        }
+    }
+
+    /// Raw reading of a single analog channel, `None` if it wasn't sampled
+    pub fn analog(&self, channel: AnalogIndicator) -> Option<u16> {
+        if !self.analog_mask.contains(channel) {
+            return None;
+        }
+        match channel {
+            | AnalogIndicator::A0 => Some(self.analog[0]),
+            | AnalogIndicator::A1 => Some(self.analog[1]),
+            | AnalogIndicator::A2 => Some(self.analog[2]),
+            | AnalogIndicator::A3 => Some(self.analog[3]),
+            | AnalogIndicator::SUPPLY_VOLTAGE => Some(self.analog[4]),
+            | _ => None,
+        }
+    }
+
+    /// Reading of a single analog channel in mV, the supply voltage included
+    pub fn millivolts(&self, channel: AnalogIndicator) -> Option<u32> {
+        let steps = if channel == AnalogIndicator::SUPPLY_VOLTAGE {
+            IoSample::SUPPLY_STEPS
+        } else {
+            0x3FF
+        };
+        self.analog(channel)
+            .map(|raw| raw as u32 * IoSample::VREF_MV / steps)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum AtCommandStatus {
     Ok,
@@ -204,24 +376,59 @@ pub enum ModemStatus {
     Dissociated,
     CoordinatorStarted,
     NetworkSecurityUpdated,
-    ModemConfigurationChanged, // while join in progress
-    EmberZigbeeStackError,
+    NetworkWokeUp,
+    NetworkWentToSleep,
     InputVoltageTooHigh,
+    RemoteManagerConnected,
+    RemoteManagerDisconnected,
+    KeyEstablishmentComplete,
+    ModemConfigurationChanged, // while join in progress
+    AccessFault,
+    FatalStackError,
+    SecureSessionEstablished,
+    SecureSessionEnded,
+    SecureSessionAuthFailed,
+    PanIdConflictDetected, // but CR=0, so no action is taken
+    PanIdChangedOnConflict,
+    RouterPanIdChanged, // by the coordinator, due to a conflict
+    NetworkWatchdogExpired, // three times
+    JoinWindowOpen,
+    JoinWindowClosed,
+    NetworkKeyRotationInitiated,
+    EmberZigbeeStackError(u8), // 0x80 and above
+    Unknown(u8),
 }
 
 impl ModemStatus {
-    fn from(val: u8) -> Result<ModemStatus, u8> {
+    fn from(val: u8) -> ModemStatus {
         match val {
-            | 0x00 => Ok(ModemStatus::HardwareReset),
-            | 0x01 => Ok(ModemStatus::WatchdogReset),
-            | 0x02 => Ok(ModemStatus::JoinedNetwork),
-            | 0x03 => Ok(ModemStatus::Dissociated),
-            | 0x06 => Ok(ModemStatus::CoordinatorStarted),
-            | 0x07 => Ok(ModemStatus::NetworkSecurityUpdated),
-            | 0x11 => Ok(ModemStatus::ModemConfigurationChanged), // while join in progress
-            | 0x80 => Ok(ModemStatus::EmberZigbeeStackError),
-            | 0x0D => Ok(ModemStatus::InputVoltageTooHigh),
-            | x => Err(x),
+            | 0x00 => ModemStatus::HardwareReset,
+            | 0x01 => ModemStatus::WatchdogReset,
+            | 0x02 => ModemStatus::JoinedNetwork,
+            | 0x03 => ModemStatus::Dissociated,
+            | 0x06 => ModemStatus::CoordinatorStarted,
+            | 0x07 => ModemStatus::NetworkSecurityUpdated,
+            | 0x0B => ModemStatus::NetworkWokeUp,
+            | 0x0C => ModemStatus::NetworkWentToSleep,
+            | 0x0D => ModemStatus::InputVoltageTooHigh,
+            | 0x0E => ModemStatus::RemoteManagerConnected,
+            | 0x0F => ModemStatus::RemoteManagerDisconnected,
+            | 0x10 => ModemStatus::KeyEstablishmentComplete,
+            | 0x11 => ModemStatus::ModemConfigurationChanged, // while join in progress
+            | 0x12 => ModemStatus::AccessFault,
+            | 0x13 => ModemStatus::FatalStackError,
+            | 0x3B => ModemStatus::SecureSessionEstablished,
+            | 0x3C => ModemStatus::SecureSessionEnded,
+            | 0x3D => ModemStatus::SecureSessionAuthFailed,
+            | 0x3E => ModemStatus::PanIdConflictDetected,
+            | 0x3F => ModemStatus::PanIdChangedOnConflict,
+            | 0x40 => ModemStatus::RouterPanIdChanged,
+            | 0x42 => ModemStatus::NetworkWatchdogExpired,
+            | 0x43 => ModemStatus::JoinWindowOpen,
+            | 0x44 => ModemStatus::JoinWindowClosed,
+            | 0x45 => ModemStatus::NetworkKeyRotationInitiated,
+            | x if x >= 0x80 => ModemStatus::EmberZigbeeStackError(x),
+            | x => ModemStatus::Unknown(x),
         }
     }
 }
@@ -248,6 +455,124 @@ impl DiscoStatus {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub enum DeviceType {
+    Coordinator,
+    Router,
+    EndDevice,
+}
+
+impl DeviceType {
+    fn from(val: u8) -> Result<DeviceType, u8> {
+        match val {
+            | 0x00 => Ok(DeviceType::Coordinator),
+            | 0x01 => Ok(DeviceType::Router),
+            | 0x02 => Ok(DeviceType::EndDevice),
+            | x => Err(x),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SourceEvent {
+    Pushbutton,
+    Joined,
+    PowerCycle, // after joining
+}
+
+impl SourceEvent {
+    fn from(val: u8) -> Result<SourceEvent, u8> {
+        match val {
+            | 0x01 => Ok(SourceEvent::Pushbutton),
+            | 0x02 => Ok(SourceEvent::Joined),
+            | 0x03 => Ok(SourceEvent::PowerCycle),
+            | x => Err(x),
+        }
+    }
+}
+
+/// Identification of the node that sent the 0x95 frame
+#[derive(Debug, PartialEq)]
+pub struct NodeIdentification<'a> {
+    pub remote_addr: Address,
+    pub remote_mac: MAC,
+    pub node_id: &'a str,
+    pub parent_addr: Address,
+    pub device_type: DeviceType,
+    pub source_event: SourceEvent,
+    pub profile_id: u16,
+    pub manufacturer_id: u16,
+}
+
+impl<'a> NodeIdentification<'a> {
+    fn from(reader: &mut Reader<'a>) -> Result<NodeIdentification<'a>, ParseError> {
+        let remote_addr = Address::from(reader)?;
+        let remote_mac = MAC::from(reader)?;
+        let node_id = reader.string("node_id")?;
+        Ok(NodeIdentification {
+            remote_addr,
+            remote_mac,
+            node_id,
+            parent_addr: Address::from(reader)?,
+            device_type: reader.status("device_type", DeviceType::from)?,
+            source_event: reader.status("source_event", SourceEvent::from)?,
+            profile_id: reader.word()?,
+            manufacturer_id: reader.word()?,
+        })
+    }
+}
+
+/// Node reported by `ND`, from the data of one of its AT command responses
+#[derive(Debug, PartialEq)]
+pub struct NodeInfo<'a> {
+    pub addr: Address,
+    pub mac: MAC,
+    pub node_id: &'a str,
+    pub parent_addr: Address,
+    pub device_type: DeviceType,
+    /// Reserved
+    pub status: u8,
+    pub profile_id: u16,
+    pub manufacturer_id: u16,
+}
+
+impl<'a> NodeInfo<'a> {
+    /// Anything after the manufacturer ID, like `DD` or the RSSI `NO` asks for, is ignored
+    pub fn parse(data: &'a [u8]) -> Result<NodeInfo<'a>, ParseError> {
+        let mut reader = Reader::new(data);
+        let addr = Address::from(&mut reader)?;
+        let mac = MAC::from(&mut reader)?;
+        let node_id = reader.string("node_id")?;
+        Ok(NodeInfo {
+            addr,
+            mac,
+            node_id,
+            parent_addr: Address::from(&mut reader)?,
+            device_type: reader.status("device_type", DeviceType::from)?,
+            status: reader.byte()?,
+            profile_id: reader.word()?,
+            manufacturer_id: reader.word()?,
+        })
+    }
+}
+
+/// Addresses of the node found by `DN`
+#[derive(Debug, PartialEq)]
+pub struct NodeAddress {
+    pub addr: Address,
+    pub mac: MAC,
+}
+
+impl NodeAddress {
+    pub fn parse(data: &[u8]) -> Result<NodeAddress, ParseError> {
+        let mut reader = Reader::new(data);
+        Ok(NodeAddress {
+            addr: Address::from(&mut reader)?,
+            mac: MAC::from(&mut reader)?,
+        })
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Outbound<'a> {
     TxRequest {
@@ -258,23 +583,45 @@ pub enum Outbound<'a> {
         options: TxOptions,
         data: &'a [u8],
     },
-    // AtCommand {
-    //     frame_id: u8,
-    //     at_cmd: [u8; 2],
-    //     params: &'a [u8],
-    // },
-    // AtCommandQueueParam {
-    //     frame_id: u8,
-    //     at_cmd: [u8; 2],
-    //     params: &'a [u8],
-    // },
-    // RemoteAtCommand {
-    //     frame_id: u8,
-    //     dest_mac: MAC,
-    //     dest_addr: Address,
-    //     at_cmd: [u8; 2],
-    //     params: &'a [u8],
-    // }
+    ExplicitTxRequest {
+        frame_id: u8,
+        dest_mac: MAC,
+        dest_addr: Address,
+        src_endpoint: u8,
+        dest_endpoint: u8,
+        cluster_id: u16,
+        profile_id: u16,
+        bc_radius: u8,
+        options: TxOptions,
+        data: &'a [u8],
+    },
+    AtCommand {
+        frame_id: u8,
+        at_cmd: [u8; 2],
+        params: &'a [u8],
+    },
+    AtCommandQueueParam {
+        frame_id: u8,
+        at_cmd: [u8; 2],
+        params: &'a [u8],
+    },
+    /// Addressed by `dest_mac`, unless it is `MAC::UNKNOWN`, then by `dest_addr`
+    /// (which should be `Address::UNKNOWN` otherwise).
+    /// Without `RemoteAtOptions::APPLY_CHANGES` the remote node only queues the value
+    /// until it receives `AC`.
+    RemoteAtCommand {
+        frame_id: u8,
+        dest_mac: MAC,
+        dest_addr: Address,
+        options: RemoteAtOptions,
+        at_cmd: [u8; 2],
+        params: &'a [u8],
+    },
+    /// Any frame, `data` is everything after the frame type
+    Raw {
+        frame_type: u8,
+        data: &'a [u8],
+    },
 }
 
 #[derive(Debug, PartialEq)]
@@ -285,6 +632,28 @@ pub enum Inbound<'a> {
         options: RxOptions,
         data: &'a [u8],
     },
+    ExplicitRxPacket {
+        source_mac: MAC,
+        source_addr: Address,
+        src_endpoint: u8,
+        dest_endpoint: u8,
+        cluster_id: u16,
+        profile_id: u16,
+        options: RxOptions,
+        data: &'a [u8],
+    },
+    IoSample {
+        source_mac: MAC,
+        source_addr: Address,
+        options: RxOptions,
+        sample: IoSample,
+    },
+    NodeIdentification {
+        source_mac: MAC,
+        source_addr: Address,
+        options: RxOptions,
+        node: NodeIdentification<'a>,
+    },
     AtCommandResponse {
         frame_id: u8,
         at_cmd: [u8; 2],
@@ -309,15 +678,20 @@ pub enum Inbound<'a> {
         status: AtCommandStatus,
         data: &'a [u8],
     },
+    /// A frame type this crate doesn't parse (yet), `data` is everything after the frame type
+    Raw {
+        frame_type: u8,
+        data: &'a [u8],
+    },
 }
 
 pub struct OutboundIterator<'a> {
-    offset: u8,
+    offset: usize,
     frame: &'a Outbound<'a>,
 }
 
 impl<'a> Outbound<'a> {
-    pub fn to_iter<'b>(&'b mut self) -> OutboundIterator<'b> {
+    pub fn to_iter<'b>(&'b self) -> OutboundIterator<'b> {
         OutboundIterator {
             offset: 0,
             frame: self,
@@ -337,7 +711,7 @@ impl<'a> Iterator for OutboundIterator<'a> {
                 ref bc_radius,
                 ref options,
                 ref data,
-            } => match self.offset as usize {
+            } => match self.offset {
                 | 0 => Some(0x10),
                 | 1 => Some(*frame_id),
                 | n if n >= 2 && n <= 9 => dest_mac.at(n - 2).ok(),
@@ -348,11 +722,87 @@ impl<'a> Iterator for OutboundIterator<'a> {
                 | n if n >= 14 && n < data.len()+14 => Some(data[n - 14]),
                 | _ => None,
             },
-            // | Outbound::AtCommand {frame_id, at_cmd, params} => Some(0x08),
-            // | Outbound::AtCommandQueueParam atParam => Some(0x09),
-            // | Outbound::RemoteAtCommand remoteAt => Some(0x17),
+            | &Outbound::ExplicitTxRequest {
+                ref frame_id,
+                ref dest_mac,
+                ref dest_addr,
+                ref src_endpoint,
+                ref dest_endpoint,
+                ref cluster_id,
+                ref profile_id,
+                ref bc_radius,
+                ref options,
+                ref data,
+            } => match self.offset {
+                | 0 => Some(0x11),
+                | 1 => Some(*frame_id),
+                | n if n >= 2 && n <= 9 => dest_mac.at(n - 2).ok(),
+                | 10 => Some(dest_addr.high),
+                | 11 => Some(dest_addr.low),
+                | 12 => Some(*src_endpoint),
+                | 13 => Some(*dest_endpoint),
+                | 14 => Some((*cluster_id >> 8) as u8),
+                | 15 => Some(*cluster_id as u8),
+                | 16 => Some((*profile_id >> 8) as u8),
+                | 17 => Some(*profile_id as u8),
+                | 18 => Some(*bc_radius),
+                | 19 => Some(options.bits()),
+                | n if n >= 20 && n < data.len() + 20 => Some(data[n - 20]),
+                | _ => None,
+            },
+            | &Outbound::AtCommand {
+                ref frame_id,
+                ref at_cmd,
+                ref params,
+            } => match self.offset {
+                | 0 => Some(0x08),
+                | 1 => Some(*frame_id),
+                | 2 => Some(at_cmd[0]),
+                | 3 => Some(at_cmd[1]),
+                | n if n >= 4 && n < params.len() + 4 => Some(params[n - 4]),
+                | _ => None,
+            },
+            | &Outbound::AtCommandQueueParam {
+                ref frame_id,
+                ref at_cmd,
+                ref params,
+            } => match self.offset {
+                | 0 => Some(0x09),
+                | 1 => Some(*frame_id),
+                | 2 => Some(at_cmd[0]),
+                | 3 => Some(at_cmd[1]),
+                | n if n >= 4 && n < params.len() + 4 => Some(params[n - 4]),
+                | _ => None,
+            },
+            | &Outbound::RemoteAtCommand {
+                ref frame_id,
+                ref dest_mac,
+                ref dest_addr,
+                ref options,
+                ref at_cmd,
+                ref params,
+            } => match self.offset {
+                | 0 => Some(0x17),
+                | 1 => Some(*frame_id),
+                | n if n >= 2 && n <= 9 => dest_mac.at(n - 2).ok(),
+                | 10 => Some(dest_addr.high),
+                | 11 => Some(dest_addr.low),
+                | 12 => Some(options.bits()),
+                | 13 => Some(at_cmd[0]),
+                | 14 => Some(at_cmd[1]),
+                | n if n >= 15 && n < params.len() + 15 => Some(params[n - 15]),
+                | _ => None,
+            },
+            | &Outbound::Raw {
+                ref frame_type,
+                ref data,
+            } => match self.offset {
+                | 0 => Some(*frame_type),
+                | n if n >= 1 && n < data.len() + 1 => Some(data[n - 1]),
+                | _ => None,
+            },
         };
-        self.offset = self.offset + 1;
+        self.offset = self.offset.saturating_add(1);
         res
     }
 }
@@ -361,29 +811,101 @@ impl<'a> ExactSizeIterator for OutboundIterator<'a> {
     fn len(&self) -> usize {
         match self.frame {
             | &Outbound::TxRequest { data, .. } => 14 + data.len(),
+            | &Outbound::ExplicitTxRequest { data, .. } => 20 + data.len(),
+            | &Outbound::AtCommand { params, .. } => 4 + params.len(),
+            | &Outbound::AtCommandQueueParam { params, .. } => 4 + params.len(),
+            | &Outbound::RemoteAtCommand { params, .. } => 15 + params.len(),
+            | &Outbound::Raw { data, .. } => 1 + data.len(),
         }
     }
 }
 
 impl<'a> Inbound<'a> {
-    pub fn parse(data: &[u8]) -> Result<Inbound, u8> {
-        let len = data.len();
-        let mut iter = data.iter();
-        match *iter.next().unwrap() {
-            | 0x90 if len > 10 => Ok(Inbound::RxPacket {
-                source_mac: MAC::from(&mut iter),
-                source_addr: Address::from(&mut iter),
-                options: RxOptions::from_bits_truncate(*iter.next().unwrap()),
-                data: iter.as_slice(),
+    pub fn parse(data: &[u8]) -> Result<Inbound, ParseError> {
+        let mut reader = Reader::new(data);
+        match reader.byte()? {
+            | 0x90 => Ok(Inbound::RxPacket {
+                source_mac: MAC::from(&mut reader)?,
+                source_addr: Address::from(&mut reader)?,
+                options: RxOptions::from_bits_truncate(reader.byte()?),
+                data: reader.rest(),
+            }),
+            | 0x91 => Ok(Inbound::ExplicitRxPacket {
+                source_mac: MAC::from(&mut reader)?,
+                source_addr: Address::from(&mut reader)?,
+                src_endpoint: reader.byte()?,
+                dest_endpoint: reader.byte()?,
+                cluster_id: reader.word()?,
+                profile_id: reader.word()?,
+                options: RxOptions::from_bits_truncate(reader.byte()?),
+                data: reader.rest(),
+            }),
+            | 0x92 => Ok(Inbound::IoSample {
+                source_mac: MAC::from(&mut reader)?,
+                source_addr: Address::from(&mut reader)?,
+                options: RxOptions::from_bits_truncate(reader.byte()?),
+                sample: IoSample::from(&mut reader)?,
+            }),
+            | 0x95 => Ok(Inbound::NodeIdentification {
+                source_mac: MAC::from(&mut reader)?,
+                source_addr: Address::from(&mut reader)?,
+                options: RxOptions::from_bits_truncate(reader.byte()?),
+                node: NodeIdentification::from(&mut reader)?,
+            }),
+            | 0x88 => Ok(Inbound::AtCommandResponse {
+                frame_id: reader.byte()?,
+                at_cmd: [reader.byte()?, reader.byte()?],
+                status: reader.status("status", AtCommandStatus::from)?,
+                data: reader.rest(),
+            }),
+            | 0x97 => Ok(Inbound::RemoteAtCommandResponse {
+                frame_id: reader.byte()?,
+                source_mac: MAC::from(&mut reader)?,
+                source_addr: Address::from(&mut reader)?,
+                at_cmd: [reader.byte()?, reader.byte()?],
+                status: reader.status("status", AtCommandStatus::from)?,
+                data: reader.rest(),
+            }),
+            | 0x8A => Ok(Inbound::ModemStatus {
+                status: ModemStatus::from(reader.byte()?),
             }),
             | 0x8B => Ok(Inbound::TransmitStatus {
-                frame_id: *iter.next().unwrap(),
-                dest_addr: Address::from(&mut iter),
-                txr_count: *iter.next().unwrap(),
-                status: TxStatus::from(*iter.next().unwrap()).unwrap(),
-                disco_status: DiscoStatus::from(*iter.next().unwrap()).unwrap(),
+                frame_id: reader.byte()?,
+                dest_addr: Address::from(&mut reader)?,
+                txr_count: reader.byte()?,
+                status: reader.status("status", TxStatus::from)?,
+                disco_status: reader.status("disco_status", DiscoStatus::from)?,
             }),
-            | n => Err(n),
+            | n => Err(ParseError::UnknownFrameType(n)),
+        }
+    }
+
+    /// Same as `parse`, but frame types this crate doesn't know come back as `Inbound::Raw`
+    pub fn parse_or_raw(data: &[u8]) -> Result<Inbound, ParseError> {
+        match Inbound::parse(data) {
+            | Err(ParseError::UnknownFrameType(frame_type)) => Ok(Inbound::Raw {
+                frame_type,
+                data: &data[1..],
+            }),
+            | res => res,
+        }
+    }
+
+    /// Frame ID of the request this frame is the status for, if any
+    pub fn frame_id(&self) -> Option<u8> {
+        match *self {
+            | Inbound::AtCommandResponse { frame_id, .. }
+            | Inbound::TransmitStatus { frame_id, .. }
+            | Inbound::RemoteAtCommandResponse { frame_id, .. } => Some(frame_id),
+            | _ => None,
+        }
+    }
+
+    /// Same as `frame_id`, from the frame data before it's parsed
+    pub fn frame_id_of(data: &[u8]) -> Option<u8> {
+        match data.first() {
+            | Some(&0x88) | Some(&0x8B) | Some(&0x97) => data.get(1).cloned(),
+            | _ => None,
         }
     }
 }
@@ -391,21 +913,37 @@ impl<'a> Inbound<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use mock::random;
 
-    // #[test]
-    // fn at_commmand_response_bd_parse_test() {
-    //     let unpacked_data = [0x88, 0x01, 0x42, 0x44, 0x00];
-    //     let parsed_data = Inbound::parse(&unpacked_data[..]).unwrap();
+    #[test]
+    fn at_commmand_response_bd_parse_test() {
+        let unpacked_data = [0x88, 0x01, 0x42, 0x44, 0x00];
+        let parsed_data = Inbound::parse(&unpacked_data[..]).unwrap();
 
-    //     let test_data = Inbound::AtCommandResponse {
-    //         frame_id: 0x01,
-    //         at_cmd: [b'B', b'D'],
-    //         status: AtCommandStatus::Ok,
-    //         data: &[],
-    //     };
+        let test_data = Inbound::AtCommandResponse {
+            frame_id: 0x01,
+            at_cmd: [b'B', b'D'],
+            status: AtCommandStatus::Ok,
+            data: &[],
+        };
 
-    //     assert_eq!(parsed_data, test_data);
-    // }
+        assert_eq!(parsed_data, test_data);
+    }
+
+    #[test]
+    fn at_commmand_response_sh_parse_test() {
+        let unpacked_data = [0x88, 0x02, 0x53, 0x48, 0x00, 0x00, 0x13, 0xA2, 0x00];
+        let parsed_data = Inbound::parse(&unpacked_data[..]).unwrap();
+
+        let test_data = Inbound::AtCommandResponse {
+            frame_id: 0x02,
+            at_cmd: [b'S', b'H'],
+            status: AtCommandStatus::Ok,
+            data: &[0x00, 0x13, 0xA2, 0x00],
+        };
+
+        assert_eq!(parsed_data, test_data);
+    }
 
     #[test]
     fn tx_status_parse_test() {
@@ -426,6 +964,32 @@ mod test {
         assert_eq!(parsed_data, test_data);
     }
 
+    #[test]
+    fn remote_at_commmand_response_parse_test() {
+        let unpacked_data = [
+            0x97, 0x27, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x53, 0x4C,
+            0x00, 0x40, 0x52, 0x2B, 0xAA,
+        ];
+        let parsed_data = Inbound::parse(&unpacked_data[..]).unwrap();
+
+        let test_data = Inbound::RemoteAtCommandResponse {
+            frame_id: 0x27,
+            source_mac: MAC {
+                high: 0x0013A200,
+                low: 0x40522BAA,
+            },
+            source_addr: Address {
+                high: 0x7D,
+                low: 0x84,
+            },
+            at_cmd: [b'S', b'L'],
+            status: AtCommandStatus::Ok,
+            data: &[0x40, 0x52, 0x2B, 0xAA],
+        };
+
+        assert_eq!(parsed_data, test_data);
+    }
+
     #[test]
     fn rx_parse_test() {
         let unpacked_data = [
@@ -449,15 +1013,300 @@ mod test {
         assert_eq!(parsed_data, test_data);
     }
 
-    // #[test]
-    // fn modem_status_parse_test() {
-    //     let unpacked_data = [0x8A, 0x00];
-    //     let parsed_data = Inbound::parse(&unpacked_data[..]).unwrap();
+    #[test]
+    fn modem_status_parse_test() {
+        let unpacked_data = [0x8A, 0x00];
+        let parsed_data = Inbound::parse(&unpacked_data[..]).unwrap();
 
-    //     let test_data = Inbound::ModemStatus {
-    //         status: ModemStatus::HardwareReset,
-    //     };
+        let test_data = Inbound::ModemStatus {
+            status: ModemStatus::HardwareReset,
+        };
 
-    //     assert_eq!(parsed_data, test_data);
-    // }
+        assert_eq!(parsed_data, test_data);
+    }
+
+    #[test]
+    fn modem_status_unknown_parse_test() {
+        assert_eq!(
+            Inbound::parse(&[0x8A, 0x43]).unwrap(),
+            Inbound::ModemStatus {
+                status: ModemStatus::JoinWindowOpen,
+            }
+        );
+        assert_eq!(
+            Inbound::parse(&[0x8A, 0x91]).unwrap(),
+            Inbound::ModemStatus {
+                status: ModemStatus::EmberZigbeeStackError(0x91),
+            }
+        );
+        assert_eq!(
+            Inbound::parse(&[0x8A, 0x7A]).unwrap(),
+            Inbound::ModemStatus {
+                status: ModemStatus::Unknown(0x7A),
+            }
+        );
+    }
+
+    #[test]
+    fn explicit_rx_parse_test() {
+        let unpacked_data = [
+            0x91, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x00, 0x01, 0x80,
+            0x31, 0x00, 0x00, 0x01, 0x12, 0x01, 0x02,
+        ];
+        let parsed_data = Inbound::parse(&unpacked_data).unwrap();
+
+        let test_data = Inbound::ExplicitRxPacket {
+            source_mac: MAC {
+                high: 0x0013A200,
+                low: 0x40522BAA,
+            },
+            source_addr: Address {
+                high: 0x7D,
+                low: 0x84,
+            },
+            src_endpoint: 0x00,
+            dest_endpoint: 0x01,
+            cluster_id: 0x8031,
+            profile_id: 0x0000,
+            options: RxOptions::PACKET_ACKNOWLEDGED,
+            data: &[0x12, 0x01, 0x02],
+        };
+
+        assert_eq!(parsed_data, test_data);
+    }
+
+    #[test]
+    fn io_sample_parse_test() {
+        let unpacked_data = [
+            0x92, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x01, 0x01, 0x00,
+            0x1C, 0x02, 0x00, 0x14, 0x02, 0x25,
+        ];
+        let parsed_data = Inbound::parse(&unpacked_data).unwrap();
+
+        let test_data = Inbound::IoSample {
+            source_mac: MAC {
+                high: 0x0013A200,
+                low: 0x40522BAA,
+            },
+            source_addr: Address {
+                high: 0x7D,
+                low: 0x84,
+            },
+            options: RxOptions::PACKET_ACKNOWLEDGED,
+            sample: IoSample {
+                digital_mask: ChannelIndicator::D2 | ChannelIndicator::D3 | ChannelIndicator::D4,
+                analog_mask: AnalogIndicator::A1,
+                digital: ChannelIndicator::D2 | ChannelIndicator::D4,
+                analog: [0, 0x0225, 0, 0, 0],
+            },
+        };
+
+        assert_eq!(parsed_data, test_data);
+
+        if let Inbound::IoSample { sample, .. } = parsed_data {
+            assert_eq!(sample.digital(ChannelIndicator::D2), Some(true));
+            assert_eq!(sample.digital(ChannelIndicator::D3), Some(false));
+            assert_eq!(sample.digital(ChannelIndicator::D0), None);
+            assert_eq!(sample.analog(AnalogIndicator::A1), Some(0x0225));
+            assert_eq!(sample.millivolts(AnalogIndicator::A1), Some(643));
+            assert_eq!(sample.analog(AnalogIndicator::SUPPLY_VOLTAGE), None);
+        }
+    }
+
+    #[test]
+    fn io_sample_analog_only_parse_test() {
+        let unpacked_data = [
+            0x92, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x01, 0x01, 0x00,
+            0x00, 0x81, 0x03, 0xFF, 0x0B, 0x64,
+        ];
+
+        match Inbound::parse(&unpacked_data).unwrap() {
+            | Inbound::IoSample { sample, .. } => {
+                assert_eq!(sample.digital(ChannelIndicator::D0), None);
+                assert_eq!(sample.millivolts(AnalogIndicator::A0), Some(1200));
+                assert_eq!(sample.analog(AnalogIndicator::SUPPLY_VOLTAGE), Some(0x0B64));
+                assert_eq!(sample.millivolts(AnalogIndicator::SUPPLY_VOLTAGE), Some(3417));
+            }
+            | frame => panic!("Unexpected frame {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn node_identification_parse_test() {
+        let unpacked_data = [
+            0x95, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x02, 0x7D, 0x84,
+            0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x4C, 0x31, 0x00, 0xFF, 0xFE, 0x01,
+            0x01, 0xC1, 0x05, 0x10, 0x1E,
+        ];
+        let parsed_data = Inbound::parse(&unpacked_data).unwrap();
+
+        let test_data = Inbound::NodeIdentification {
+            source_mac: MAC {
+                high: 0x0013A200,
+                low: 0x40522BAA,
+            },
+            source_addr: Address {
+                high: 0x7D,
+                low: 0x84,
+            },
+            options: RxOptions::BROADCAST_PACKET,
+            node: NodeIdentification {
+                remote_addr: Address {
+                    high: 0x7D,
+                    low: 0x84,
+                },
+                remote_mac: MAC {
+                    high: 0x0013A200,
+                    low: 0x40522BAA,
+                },
+                node_id: "L1",
+                parent_addr: Address::UNKNOWN,
+                device_type: DeviceType::Router,
+                source_event: SourceEvent::Pushbutton,
+                profile_id: 0xC105,
+                manufacturer_id: 0x101E,
+            },
+        };
+
+        assert_eq!(parsed_data, test_data);
+    }
+
+    #[test]
+    fn node_info_parse_test() {
+        // trailing RSSI
+        let data = [
+            0x7D, 0x84, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x4C, 0x31, 0x00, 0xFF,
+            0xFE, 0x01, 0x00, 0xC1, 0x05, 0x10, 0x1E, 0x28,
+        ];
+        let test_data = NodeInfo {
+            addr: Address {
+                high: 0x7D,
+                low: 0x84,
+            },
+            mac: MAC {
+                high: 0x0013A200,
+                low: 0x40522BAA,
+            },
+            node_id: "L1",
+            parent_addr: Address::UNKNOWN,
+            device_type: DeviceType::Router,
+            status: 0,
+            profile_id: 0xC105,
+            manufacturer_id: 0x101E,
+        };
+        assert_eq!(NodeInfo::parse(&data), Ok(test_data));
+        assert_eq!(
+            NodeInfo::parse(&data[..12]),
+            Err(ParseError::Truncated { needed: 13, got: 12 })
+        );
+
+        let data = [0x7D, 0x84, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA];
+        assert_eq!(
+            NodeAddress::parse(&data),
+            Ok(NodeAddress {
+                addr: Address {
+                    high: 0x7D,
+                    low: 0x84,
+                },
+                mac: MAC {
+                    high: 0x0013A200,
+                    low: 0x40522BAA,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn raw_parse_test() {
+        let unpacked_data = [0xA1, 0x00, 0x13, 0xA2, 0x00];
+        assert_eq!(Inbound::parse(&unpacked_data), Err(ParseError::UnknownFrameType(0xA1)));
+        let parsed_data = Inbound::parse_or_raw(&unpacked_data).unwrap();
+
+        let test_data = Inbound::Raw {
+            frame_type: 0xA1,
+            data: &[0x00, 0x13, 0xA2, 0x00],
+        };
+
+        assert_eq!(parsed_data, test_data);
+    }
+
+    #[test]
+    fn malformed_parse_test() {
+        assert_eq!(
+            Inbound::parse(&[0x90, 0x00, 0x13]),
+            Err(ParseError::Truncated { needed: 9, got: 3 })
+        );
+        assert_eq!(
+            Inbound::parse(&[0x8B, 0x01, 0x42, 0x43, 0x02, 0x99, 0x01]),
+            Err(ParseError::UnknownStatus {
+                field: "status",
+                value: 0x99,
+            })
+        );
+        assert_eq!(Inbound::parse(&[]), Err(ParseError::Truncated { needed: 1, got: 0 }));
+    }
+
+    const VALID_FRAMES: [&[u8]; 5] = [
+        &[0x8B, 0x01, 0x42, 0x43, 0x02, 0x23, 0x01],
+        &[0x90, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x22],
+        &[
+            0x92, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x01, 0x01, 0x00,
+            0x1C, 0x02, 0x00, 0x14, 0x02, 0x25,
+        ],
+        &[
+            0x95, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x02, 0x7D, 0x84,
+            0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x4C, 0x31, 0x00, 0xFF, 0xFE, 0x01,
+            0x01, 0xC1, 0x05, 0x10, 0x1E,
+        ],
+        &[
+            0x97, 0x27, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x53, 0x4C,
+            0x00,
+        ],
+    ];
+
+    #[test]
+    fn truncated_parse_test() {
+        for frame in VALID_FRAMES.iter() {
+            assert!(Inbound::parse(frame).is_ok());
+            for len in 0..frame.len() {
+                match Inbound::parse(&frame[..len]) {
+                    | Err(ParseError::Truncated { needed, got }) => {
+                        assert!(needed > len);
+                        assert_eq!(got, len);
+                    }
+                    | res => panic!("Unexpected result {:?} for {:?}", res, &frame[..len]),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arbitrary_parse_test() {
+        let types = [0x88, 0x8A, 0x8B, 0x90, 0x91, 0x92, 0x95, 0x97];
+        let mut state = 0x2545F491;
+        let mut data = [0u8; 64];
+        for _ in 0..100_000 {
+            let len = (random(&mut state) % data.len() as u32) as usize;
+            for b in data[..len].iter_mut() {
+                *b = random(&mut state) as u8;
+            }
+            if len > 0 {
+                data[0] = types[(random(&mut state) % types.len() as u32) as usize];
+            }
+            let _ = Inbound::parse_or_raw(&data[..len]);
+        }
+        for frame in VALID_FRAMES.iter() {
+            // every single byte corruption
+            let mut data = [0u8; 64];
+            data[..frame.len()].copy_from_slice(frame);
+            for i in 0..frame.len() {
+                let original = data[i];
+                for b in 0..256 {
+                    data[i] = b as u8;
+                    let _ = Inbound::parse_or_raw(&data[..frame.len()]);
+                }
+                data[i] = original;
+            }
+        }
+    }
 }
@@ -0,0 +1,298 @@
+//! Network join state, from modem status frames and `AI`.
+
+use embedded_hal::blocking::serial::Write as BlockingWrite;
+use embedded_hal::serial::Read;
+use embedded_hal::timer::CountDown;
+
+use at::{self, Association};
+use frame::{self, ModemStatus};
+use {ApiError, XBeeApiUart};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// Nothing heard yet, or the module was reset
+    Unknown,
+    /// Not associated yet, as last reported by `AI`
+    Joining(Association),
+    Joined,
+    /// Left the network or lost it
+    Dissociated,
+}
+
+/// Operating network parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    /// `CH`
+    pub channel: u8,
+    /// `OP`
+    pub pan_id: u64,
+    /// `OI`
+    pub pan_id16: u16,
+}
+
+/// Tracks whether the module is on a network, so that nothing gets sent before it joined.
+/// Frames received elsewhere should be passed to `update`.
+pub struct JoinManager {
+    state: State,
+    network: Option<Network>,
+}
+
+impl Default for JoinManager {
+    fn default() -> JoinManager {
+        JoinManager::new()
+    }
+}
+
+impl JoinManager {
+    pub fn new() -> JoinManager {
+        JoinManager {
+            state: State::Unknown,
+            network: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_joined(&self) -> bool {
+        self.state == State::Joined
+    }
+
+    /// Last read with `read_network`, forgotten once the module leaves the network
+    pub fn network(&self) -> Option<Network> {
+        self.network
+    }
+
+    /// Follows `JoinedNetwork`, `CoordinatorStarted`, `Dissociated` and resets
+    pub fn update(&mut self, frame: &frame::Inbound) {
+        if let frame::Inbound::ModemStatus { ref status } = *frame {
+            match *status {
+                | ModemStatus::JoinedNetwork | ModemStatus::CoordinatorStarted => {
+                    self.state = State::Joined
+                }
+                | ModemStatus::Dissociated => self.dissociated(State::Dissociated),
+                | ModemStatus::HardwareReset | ModemStatus::WatchdogReset => {
+                    self.dissociated(State::Unknown)
+                }
+                | _ => {}
+            }
+        }
+    }
+
+    /// Reads `AI`. Frames received meanwhile are passed to `update`, then to `handler`.
+    pub fn poll<'a, E, U, H, T>(
+        &mut self,
+        xbee: &mut XBeeApiUart<'a, U>,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<Association, ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let association = {
+            let mut forward = |frame: frame::Inbound| {
+                self.update(&frame);
+                handler(frame);
+            };
+            xbee.get(at::Ai, &mut forward, timer, timeout)?
+        };
+        self.state = match association {
+            | Association::Joined => State::Joined,
+            | association => State::Joining(association),
+        };
+        Ok(association)
+    }
+
+    /// Returns once the module is on a network, or fails with `ApiError::Timeout`,
+    /// with the reason in `state`. Unrelated frames are passed to `handler`.
+    pub fn wait_for_join<'a, E, U, H, T>(
+        &mut self,
+        xbee: &mut XBeeApiUart<'a, U>,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        if self.poll(xbee, handler, timer, timeout.clone())? == Association::Joined {
+            return Ok(());
+        }
+        timer.start(timeout.clone());
+        while !self.is_joined() {
+            let expired = match xbee.poll_frame() {
+                | Ok(Some(frame)) => {
+                    self.update(&frame);
+                    handler(frame);
+                    false
+                }
+                | Ok(None) => false,
+                | Err(::nb::Error::WouldBlock) => timer.wait().is_ok(),
+                | Err(::nb::Error::Other(e)) => return Err(e),
+            };
+            if expired {
+                // tells why in `state`
+                self.poll(xbee, handler, timer, timeout)?;
+                return if self.is_joined() { Ok(()) } else { Err(ApiError::Timeout) };
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the network with `CB 4`
+    pub fn leave<'a, E, U, H, T>(
+        &mut self,
+        xbee: &mut XBeeApiUart<'a, U>,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        xbee.set(at::Cb, &4, handler, timer, timeout)?;
+        self.dissociated(State::Dissociated);
+        Ok(())
+    }
+
+    /// Resets the network layer with `NR 0`, which leaves and then joins again.
+    /// Follow with `wait_for_join`.
+    pub fn rejoin<'a, E, U, H, T>(
+        &mut self,
+        xbee: &mut XBeeApiUart<'a, U>,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        xbee.set(at::Nr, &0, handler, timer, timeout)?;
+        self.dissociated(State::Unknown);
+        Ok(())
+    }
+
+    /// Reads the operating channel and PAN IDs, meaningful once joined
+    pub fn read_network<'a, E, U, H, T>(
+        &mut self,
+        xbee: &mut XBeeApiUart<'a, U>,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<Network, ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let network = Network {
+            channel: xbee.get(at::Ch, handler, timer, timeout.clone())?,
+            pan_id: xbee.get(at::Op, handler, timer, timeout.clone())?,
+            pan_id16: xbee.get(at::Oi, handler, timer, timeout)?,
+        };
+        self.network = Some(network);
+        Ok(network)
+    }
+
+    fn dissociated(&mut self, state: State) {
+        self.state = state;
+        self.network = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mock::{Module, PollTimer};
+    use serializer::ApiMode;
+
+    #[test]
+    fn update_test() {
+        let mut join = JoinManager::new();
+        assert_eq!(join.state(), State::Unknown);
+        join.update(&frame::Inbound::ModemStatus {
+            status: ModemStatus::CoordinatorStarted,
+        });
+        assert!(join.is_joined());
+        join.update(&frame::Inbound::ModemStatus {
+            status: ModemStatus::Dissociated,
+        });
+        assert_eq!(join.state(), State::Dissociated);
+    }
+
+    #[test]
+    fn wait_for_join_test() {
+        let mut module = Module::new(&[
+            (*b"AI", 0xFF),
+            (*b"CH", 0x0B),
+            (*b"OP", 0x1234),
+            (*b"OI", 0xABCD),
+        ]);
+        let mut join = JoinManager::new();
+        let mut timer = PollTimer { remaining: 0 };
+        {
+            let mut buffer = [0; 64];
+            let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+            match join.wait_for_join(&mut xbee, &mut |_| {}, &mut timer, 10) {
+                | Err(ApiError::Timeout) => {}
+                | res => panic!("{:?}", res),
+            }
+            assert_eq!(join.state(), State::Joining(Association::Scanning));
+        }
+        module.store(*b"AI", 0);
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+        join.wait_for_join(&mut xbee, &mut |_| {}, &mut timer, 10).unwrap();
+        assert!(join.is_joined());
+        let network = join.read_network(&mut xbee, &mut |_| {}, &mut timer, 10).unwrap();
+        assert_eq!(
+            network,
+            Network {
+                channel: 0x0B,
+                pan_id: 0x1234,
+                pan_id16: 0xABCD,
+            }
+        );
+        join.leave(&mut xbee, &mut |_| {}, &mut timer, 10).unwrap();
+        assert_eq!(join.state(), State::Dissociated);
+        assert_eq!(join.network(), None);
+    }
+
+    #[test]
+    fn status_during_poll_test() {
+        let mut module = Module::new(&[
+            (*b"AI", 0),
+            (*b"CH", 0x0B),
+            (*b"OP", 0x1234),
+            (*b"OI", 0xABCD),
+        ]);
+        let mut join = JoinManager::new();
+        let mut timer = PollTimer { remaining: 0 };
+        {
+            let mut buffer = [0; 64];
+            let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+            join.read_network(&mut xbee, &mut |_| {}, &mut timer, 10).unwrap();
+        }
+        // lost the network, and joined again before the AI response
+        module.respond(&[0x8A, 0x03]);
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+        let mut statuses = 0;
+        join.wait_for_join(&mut xbee, &mut |_| statuses += 1, &mut timer, 10).unwrap();
+        assert_eq!(statuses, 1);
+        assert!(join.is_joined());
+        assert_eq!(join.network(), None);
+    }
+}
@@ -6,13 +6,25 @@ extern crate embedded_hal;
 #[macro_use]
 extern crate nb;
 extern crate heapless;
+#[cfg(test)]
+extern crate void;
 
+pub mod at;
+pub mod baud;
+pub mod cache;
+pub mod discovery;
 pub mod frame;
+pub mod join;
+#[cfg(test)]
+mod mock;
+pub mod profile;
 pub mod serializer;
 
 use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::blocking::serial::Write as BlockingWrite;
 use embedded_hal::serial::{Read, Write};
+use embedded_hal::timer::CountDown;
+use core::str;
 
 pub struct XBeeTransparent<'a, 'b, U: 'a, D: 'b> {
     serial: &'a mut U,
@@ -21,15 +33,157 @@ pub struct XBeeTransparent<'a, 'b, U: 'a, D: 'b> {
     guard_time: u16,
 }
 
-trait XBeeApi {
+/// A module in API mode
+pub trait XBeeApi {
     type Error;
 
-    fn send(&mut self, &frame::Outbound) -> Result<(), Self::Error>;
+    fn send(&mut self, frame: &frame::Outbound) -> Result<(), Self::Error>;
+    /// Blocks until a whole frame has been received
     fn receive(&mut self) -> Result<frame::Inbound, Self::Error>;
+    /// Returns `nb::Error::WouldBlock` until a whole frame has been received
+    fn poll_receive(&mut self) -> nb::Result<frame::Inbound, Self::Error>;
 }
 
+/// Frames are received into the buffer passed to `new`, see `serializer::FrameDecoder`.
 pub struct XBeeApiUart<'a, U: 'a> {
     serial: &'a mut U,
+    mode: serializer::ApiMode,
+    decoder: serializer::FrameDecoder<'a>,
+    ids: FrameIds,
+}
+
+/// Allocates frame IDs round-robin, skipping 0 (no response) and IDs still in flight
+pub struct FrameIds {
+    last: u8,
+    in_flight: [u32; 8],
+}
+
+impl Default for FrameIds {
+    fn default() -> FrameIds {
+        FrameIds::new()
+    }
+}
+
+impl FrameIds {
+    pub fn new() -> FrameIds {
+        FrameIds {
+            last: 0,
+            in_flight: [0; 8],
+        }
+    }
+
+    /// `None` if all 255 IDs are in flight
+    pub fn allocate(&mut self) -> Option<u8> {
+        let mut id = self.last;
+        for _ in 0..255 {
+            id = if id == 0xFF { 1 } else { id + 1 };
+            if !self.is_in_flight(id) {
+                self.in_flight[(id >> 5) as usize] |= 1 << (id & 0x1F);
+                self.last = id;
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    pub fn release(&mut self, id: u8) {
+        self.in_flight[(id >> 5) as usize] &= !(1 << (id & 0x1F));
+    }
+
+    pub fn is_in_flight(&self, id: u8) -> bool {
+        self.in_flight[(id >> 5) as usize] & 1 << (id & 0x1F) != 0
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError<E> {
+    Serialization(serializer::SerializationError<E>),
+    Deserialization(serializer::DeserializationError<E>),
+    AtCommand {
+        at_cmd: [u8; 2],
+        status: frame::AtCommandStatus,
+    },
+    Timeout,
+    /// All frame IDs are in flight
+    NoFrameId,
+    Command(CommandError<E>),
+    /// The response didn't decode to the command's value
+    Value(at::ValueError),
+    /// The frame carrying our frame ID wasn't the expected response type
+    UnexpectedResponse,
+}
+
+#[derive(Debug)]
+pub enum CommandError<E> {
+    Timeout,
+    Unexpected(u8),
+    /// The module replied "ERROR"
+    Error,
+    /// The reply line doesn't fit the buffer
+    WouldOverflow,
+    Other(E),
+}
+
+/// A `\r`-terminated reply line in command mode
+#[derive(Debug, PartialEq)]
+pub enum AtReply<'r> {
+    Ok,
+    Error,
+    /// Anything else as sent, e.g. a parameter value or the node identifier,
+    /// see `as_hex` for numeric parameters
+    Value(&'r str),
+    /// Separates records in multi-line replies
+    Empty,
+}
+
+impl<'r> AtReply<'r> {
+    // fails with the first byte that is not valid UTF-8
+    fn parse(line: &'r [u8]) -> Result<AtReply<'r>, u8> {
+        match line {
+            | b"" => Ok(AtReply::Empty),
+            | b"OK" => Ok(AtReply::Ok),
+            | b"ERROR" => Ok(AtReply::Error),
+            | _ => match str::from_utf8(line) {
+                | Ok(text) => Ok(AtReply::Value(text)),
+                | Err(e) => Err(line[e.valid_up_to()]),
+            },
+        }
+    }
+
+    /// The value as a number, numeric parameters are reported in up to 16 hex digits.
+    /// Only the caller knows if it is one, a node identifier like "ED" also reads as hex.
+    pub fn as_hex(&self) -> Option<u64> {
+        match *self {
+            | AtReply::Value(text) if text.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                u64::from_str_radix(text, 16).ok()
+            }
+            | _ => None,
+        }
+    }
+}
+
+impl<E> From<serializer::SerializationError<E>> for ApiError<E> {
+    fn from(e: serializer::SerializationError<E>) -> ApiError<E> {
+        ApiError::Serialization(e)
+    }
+}
+
+impl<E> From<CommandError<E>> for ApiError<E> {
+    fn from(e: CommandError<E>) -> ApiError<E> {
+        ApiError::Command(e)
+    }
+}
+
+impl<E> From<serializer::FrameError> for ApiError<E> {
+    fn from(e: serializer::FrameError) -> ApiError<E> {
+        ApiError::Deserialization(e.into())
+    }
+}
+
+impl<E> From<serializer::DeserializationError<E>> for ApiError<E> {
+    fn from(e: serializer::DeserializationError<E>) -> ApiError<E> {
+        ApiError::Deserialization(e)
+    }
 }
 
 impl<'a, 'b, E, U, D> XBeeTransparent<'a, 'b, U, D>
@@ -51,34 +205,193 @@ where
         }
     }
 
-    pub fn enter_command_mode(&mut self) -> Result<(), E> {
+    /// `timeout` covers the whole "OK" reply, which only comes after another guard time
+    pub fn enter_command_mode<T>(
+        &mut self,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), CommandError<E>>
+    where
+        T: CountDown,
+    {
         // wait for guard time
         self.timer.delay_ms(self.guard_time);
         // send command character x3
-        self.serial.bwrite_all(&[self.cmd_char; 3])?;
+        self.serial
+            .bwrite_all(&[self.cmd_char; 3])
+            .map_err(CommandError::Other)?;
         // wait for "OK"
+        timer.start(timeout);
+        self.expect_ok(timer)
+    }
+
+    /// Leaves command mode with `ATCN`.
+    /// Without it the module drops back to transparent mode after its `CT` timeout.
+    pub fn exit_command_mode<T>(
+        &mut self,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), CommandError<E>>
+    where
+        T: CountDown,
+    {
+        self.send_command([b'C', b'N'], &[])?;
+        timer.start(timeout);
+        self.expect_ok(timer)
+    }
+
+    /// Sends `ATxx[param]\r` and reads the single line reply into `buffer`.
+    /// `param` is text, e.g. `b"3"` or `b"13A200"`;
+    /// an "ERROR" reply fails with `CommandError::Error`.
+    pub fn at_command<'r, T>(
+        &mut self,
+        at_cmd: [u8; 2],
+        param: &[u8],
+        buffer: &'r mut [u8],
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<AtReply<'r>, CommandError<E>>
+    where
+        T: CountDown,
+    {
+        self.send_command(at_cmd, param)?;
+        timer.start(timeout);
+        match self.read_reply(buffer, timer)? {
+            | AtReply::Error => Err(CommandError::Error),
+            | reply => Ok(reply),
+        }
+    }
+
+    /// Same as `at_command`, for commands like `ATND` that reply with records of several lines.
+    /// Each line is passed to `cont`, records are separated by `AtReply::Empty`.
+    /// The reply ends with an empty line that follows another one or comes first.
+    /// `timeout` applies to each line.
+    pub fn at_command_lines<T, C>(
+        &mut self,
+        at_cmd: [u8; 2],
+        param: &[u8],
+        buffer: &mut [u8],
+        cont: &mut C,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), CommandError<E>>
+    where
+        T: CountDown,
+        T::Time: Clone,
+        C: FnMut(AtReply),
+    {
+        self.send_command(at_cmd, param)?;
+        let mut empty = true;
         loop {
-            match self.serial.read() {
-                Ok(b'O') => break,
-                Ok(_) => panic!("Got other character while waiting for OK"), // TODO: error
-                Err(nb::Error::WouldBlock) => {} // keep blocking
-                Err(_) => panic!("Some error while waiting for OK"), // return Err(e.into()),
+            timer.start(timeout.clone());
+            match self.read_reply(buffer, timer)? {
+                | AtReply::Error => return Err(CommandError::Error),
+                | AtReply::Empty if empty => return Ok(()),
+                | reply => {
+                    empty = reply == AtReply::Empty;
+                    cont(reply);
+                }
             }
         }
+    }
+
+    fn send_command(&mut self, at_cmd: [u8; 2], param: &[u8]) -> Result<(), CommandError<E>> {
+        self.serial.bwrite_all(b"AT").map_err(CommandError::Other)?;
+        self.serial.bwrite_all(&at_cmd).map_err(CommandError::Other)?;
+        self.serial.bwrite_all(param).map_err(CommandError::Other)?;
+        self.serial.bwrite_all(b"\r").map_err(CommandError::Other)
+    }
+
+    fn expect_ok<T: CountDown>(&mut self, timer: &mut T) -> Result<(), CommandError<E>> {
+        let mut buffer = [0; 8];
+        let line = self.read_line(&mut buffer, timer)?;
+        match line {
+            | b"OK" => Ok(()),
+            | b"ERROR" => Err(CommandError::Error),
+            | _ => Err(CommandError::Unexpected(line.first().cloned().unwrap_or(b'\r'))),
+        }
+    }
+
+    fn read_reply<'r, T: CountDown>(
+        &mut self,
+        buffer: &'r mut [u8],
+        timer: &mut T,
+    ) -> Result<AtReply<'r>, CommandError<E>> {
+        AtReply::parse(self.read_line(buffer, timer)?).map_err(CommandError::Unexpected)
+    }
+
+    fn read_line<'r, T: CountDown>(
+        &mut self,
+        buffer: &'r mut [u8],
+        timer: &mut T,
+    ) -> Result<&'r [u8], CommandError<E>> {
+        let mut len = 0;
+        loop {
+            match self.read_byte(timer)? {
+                | b'\r' => break,
+                | byte => {
+                    *buffer.get_mut(len).ok_or(CommandError::WouldOverflow)? = byte;
+                    len += 1;
+                }
+            }
+        }
+        Ok(&buffer[..len])
+    }
+
+    fn read_byte<T: CountDown>(&mut self, timer: &mut T) -> Result<u8, CommandError<E>> {
         loop {
             match self.serial.read() {
-                Ok(b'K') => break,
-                Ok(_) => panic!("Got other character while waiting for OK"), // TODO: error
-                Err(nb::Error::WouldBlock) => {} // keep blocking
-                Err(_) => panic!("Some error while waiting for OK"), // return Err(e.into()),
+                | Ok(byte) => return Ok(byte),
+                | Err(nb::Error::WouldBlock) => if timer.wait().is_ok() {
+                    return Err(CommandError::Timeout);
+                },
+                | Err(nb::Error::Other(e)) => return Err(CommandError::Other(e)),
             }
         }
-        Ok(())
     }
 
-    pub fn to_api(self) -> XBeeApiUart<'a, U> {
-        // TODO: AT command
-        XBeeApiUart::new(self.serial)
+    /// Switches the module to API `mode`, see `switch_to_api`
+    pub fn to_api<T>(
+        mut self,
+        mode: serializer::ApiMode,
+        persist: bool,
+        buffer: &'a mut [u8],
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<XBeeApiUart<'a, U>, CommandError<E>>
+    where
+        T: CountDown,
+        T::Time: Clone,
+    {
+        self.enter_command_mode(timer, timeout.clone())?;
+        self.switch_to_api(mode, persist, timer, timeout)?;
+        Ok(XBeeApiUart::new(self.serial, mode, buffer))
+    }
+
+    /// In command mode, sets `AP` to `mode`, persists it with `WR` if `persist` is set,
+    /// reads it back and leaves command mode, which applies the change.
+    pub fn switch_to_api<T>(
+        &mut self,
+        mode: serializer::ApiMode,
+        persist: bool,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), CommandError<E>>
+    where
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let mut buffer = [0; 8];
+        self.at_command([b'A', b'P'], &[b'0' + mode.ap()], &mut buffer, timer, timeout.clone())?;
+        if persist {
+            self.at_command([b'W', b'R'], &[], &mut buffer, timer, timeout.clone())?;
+        }
+        let confirmed = self.at_command([b'A', b'P'], &[], &mut buffer, timer, timeout.clone())?
+            .as_hex() == Some(mode.ap() as u64);
+        if !confirmed {
+            return Err(CommandError::Unexpected(buffer[0]));
+        }
+        self.exit_command_mode(timer, timeout)
     }
 }
 
@@ -112,37 +425,712 @@ impl<'a, E, U> XBeeApiUart<'a, U>
 where
     U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
 {
-    pub fn new(uart: &'a mut U) -> XBeeApiUart<'a, U> {
-        // TODO: check that we are in API mode and if not, switch
-        XBeeApiUart{ serial: uart }
+    /// Assumes the module is in API `mode`, see `start` otherwise
+    pub fn new(
+        uart: &'a mut U,
+        mode: serializer::ApiMode,
+        buffer: &'a mut [u8],
+    ) -> XBeeApiUart<'a, U> {
+        XBeeApiUart {
+            serial: uart,
+            mode,
+            decoder: serializer::FrameDecoder::new(mode, buffer),
+            ids: FrameIds::new(),
+        }
+    }
+
+    /// Starts the driver on `xbee`'s port however the module was left.
+    /// Tries command mode first, as API modes ignore the command sequence, and switches
+    /// from transparent mode to `mode`. Otherwise detects the API mode in use and
+    /// switches to `mode` if it differs. `persist` writes any change with `WR`.
+    /// `timeout` applies to each reply.
+    pub fn start<'b, D, H, T>(
+        mut xbee: XBeeTransparent<'a, 'b, U, D>,
+        mode: serializer::ApiMode,
+        persist: bool,
+        buffer: &'a mut [u8],
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<XBeeApiUart<'a, U>, ApiError<E>>
+    where
+        D: DelayMs<u16>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let transparent = match xbee.enter_command_mode(timer, timeout.clone()) {
+            | Ok(()) => {
+                xbee.switch_to_api(mode, persist, timer, timeout.clone())?;
+                true
+            }
+            | Err(CommandError::Other(e)) => return Err(CommandError::Other(e).into()),
+            // anything else, like frames received in API mode, means it's not in transparent mode
+            | Err(_) => false,
+        };
+        let mut xbee = XBeeApiUart::new(xbee.serial, mode, buffer);
+        if !transparent && xbee.detect_mode(handler, timer, timeout.clone())? != mode {
+            xbee.switch_mode(mode, persist, handler, timer, timeout)?;
+        }
+        Ok(xbee)
+    }
+
+    pub fn mode(&self) -> serializer::ApiMode {
+        self.mode
+    }
+
+    /// Queries `AP` in both API modes, starting with the current one, and keeps
+    /// the one the module answered in. Fails with `ApiError::Timeout` if neither
+    /// got an answer, e.g. in transparent mode.
+    pub fn detect_mode<H, T>(
+        &mut self,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<serializer::ApiMode, ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let other = match self.mode {
+            | serializer::ApiMode::Unescaped => serializer::ApiMode::Escaped,
+            | serializer::ApiMode::Escaped => serializer::ApiMode::Unescaped,
+        };
+        match self.query_ap(handler, timer, timeout.clone()) {
+            | Err(ApiError::Timeout) => {}
+            | res => return res,
+        }
+        self.set_mode(other);
+        self.query_ap(handler, timer, timeout)
+    }
+
+    /// Switches between the API modes with `AP`, persisting the change with `WR` if `persist`
+    /// is set, and confirms it by reading `AP` back in the new mode.
+    pub fn switch_mode<H, T>(
+        &mut self,
+        mode: serializer::ApiMode,
+        persist: bool,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        self.set_ap(mode.ap(), persist, handler, timer, timeout.clone())?;
+        self.set_mode(mode);
+        if self.query_ap(handler, timer, timeout)? != mode {
+            return Err(ApiError::UnexpectedResponse);
+        }
+        Ok(())
+    }
+
+    // changes the framing on our side only
+    fn set_mode(&mut self, mode: serializer::ApiMode) {
+        self.mode = mode;
+        self.decoder.set_mode(mode);
+    }
+
+    fn query_ap<H, T>(
+        &mut self,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<serializer::ApiMode, ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let frame = |frame_id| frame::Outbound::AtCommand {
+            frame_id,
+            at_cmd: [b'A', b'P'],
+            params: &[],
+        };
+        let mode = match self.send_and_wait(frame, handler, timer, timeout)? {
+            | frame::Inbound::AtCommandResponse {
+                status: frame::AtCommandStatus::Ok,
+                data: &[1],
+                ..
+            } => serializer::ApiMode::Unescaped,
+            | frame::Inbound::AtCommandResponse {
+                status: frame::AtCommandStatus::Ok,
+                data: &[2],
+                ..
+            } => serializer::ApiMode::Escaped,
+            | _ => return Err(ApiError::UnexpectedResponse),
+        };
+        self.set_mode(mode);
+        Ok(mode)
+    }
+
+    // queued, so that the change only applies with `WR` or `AC` and these still get through
+    fn set_ap<H, T>(
+        &mut self,
+        ap: u8,
+        persist: bool,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        self.at_command(true, [b'A', b'P'], &[ap], handler, timer, timeout.clone())?;
+        self.commit(persist, handler, timer, timeout)
+    }
+
+    /// Applies queued changes with `AC`, or with `WR` if `persist` is set, which also writes them
+    pub fn commit<H, T>(
+        &mut self,
+        persist: bool,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let apply = if persist { [b'W', b'R'] } else { [b'A', b'C'] };
+        self.at_command(false, apply, &[], handler, timer, timeout)
+    }
+
+    /// Queries `cmd`, e.g. `get(at::Sh, ..)` gives the serial number high as a `u32`
+    pub fn get<C, H, T>(
+        &mut self,
+        cmd: C,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<C::Value, ApiError<E>>
+    where
+        C: at::Get,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        match self.send_and_wait(|frame_id| at::query(cmd, frame_id), handler, timer, timeout)? {
+            | frame::Inbound::AtCommandResponse {
+                status: frame::AtCommandStatus::Ok,
+                data,
+                ..
+            } => C::decode(data).map_err(ApiError::Value),
+            | frame::Inbound::AtCommandResponse { status, .. } => Err(ApiError::AtCommand {
+                at_cmd: C::AT,
+                status,
+            }),
+            | _ => Err(ApiError::UnexpectedResponse),
+        }
+    }
+
+    /// Sets `cmd` to `value`, which applies right away
+    pub fn set<C, H, T>(
+        &mut self,
+        cmd: C,
+        value: &C::Value,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        C: at::Set,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let mut param = [0; at::MAX_PARAM];
+        let response = self.send_and_wait(
+            |frame_id| at::set(cmd, frame_id, value, &mut param),
+            handler,
+            timer,
+            timeout,
+        )?;
+        at_status(C::AT, response)
+    }
+
+    /// Same as `set`, but the change only applies with `commit`
+    /// or the next command that isn't queued
+    pub fn queue<C, H, T>(
+        &mut self,
+        _: C,
+        value: &C::Value,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        C: at::Set,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let mut param = [0; at::MAX_PARAM];
+        let params = C::encode(value, &mut param);
+        let response = self.send_and_wait(
+            |frame_id| frame::Outbound::AtCommandQueueParam {
+                frame_id,
+                at_cmd: C::AT,
+                params,
+            },
+            handler,
+            timer,
+            timeout,
+        )?;
+        at_status(C::AT, response)
     }
 
+    /// `send` doesn't allocate frame IDs: take them from here, or use `send_and_wait`.
+    /// They are released when the response is received.
+    pub fn frame_ids(&mut self) -> &mut FrameIds {
+        &mut self.ids
+    }
+
+    /// Sends the frame built by `frame` with a newly allocated frame ID and
+    /// waits up to `timeout` for the status frame carrying that ID.
+    /// Unrelated frames received meanwhile are passed to `handler`.
+    pub fn send_and_wait<'f, F, H, T>(
+        &mut self,
+        frame: F,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<frame::Inbound, ApiError<E>>
+    where
+        F: FnOnce(u8) -> frame::Outbound<'f>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let id = self.ids.allocate().ok_or(ApiError::NoFrameId)?;
+        if let Err(e) = self.send(&frame(id)) {
+            self.ids.release(id);
+            return Err(e);
+        }
+        timer.start(timeout);
+        loop {
+            // the ID is checked before parsing, so that each frame is parsed once
+            let matched = match self.fill() {
+                | Ok(()) => if self.decoder.frame_id() == Some(id) {
+                    Ok(true)
+                } else {
+                    // bad frames are skipped, as in `poll_frame`
+                    if let Ok(frame) = self.take_frame() {
+                        handler(frame);
+                    }
+                    Ok(false)
+                },
+                | Err(nb::Error::WouldBlock) => if timer.wait().is_ok() {
+                    Err(ApiError::Timeout)
+                } else {
+                    Ok(false)
+                },
+                | Err(nb::Error::Other(e)) => Err(e),
+            };
+            match matched {
+                | Ok(true) => break,
+                | Ok(false) => {}
+                | Err(e) => {
+                    self.ids.release(id);
+                    return Err(e);
+                }
+            }
+        }
+        // in case the response doesn't parse
+        self.ids.release(id);
+        self.take_frame()
+    }
+
+    /// Same as `poll_receive`, but a frame that doesn't decode is `None`: the decoder
+    /// has resynced and the next one may be fine. Only a UART error fails.
+    pub fn poll_frame(&mut self) -> nb::Result<Option<frame::Inbound>, ApiError<E>> {
+        self.fill()?;
+        Ok(self.take_frame().ok())
+    }
+
+    /// Bytes and frames the receiver discarded, see `serializer::FrameDecoder`
+    pub fn dropped(&self) -> (u32, u32) {
+        (self.decoder.dropped_bytes(), self.decoder.dropped_frames())
+    }
+
+    /// Switches the module to transparent mode with `AP`, persisting the change with `WR`
+    /// if `persist` is set. The module's acknowledgement is the only confirmation.
+    /// Unrelated frames received meanwhile are discarded.
     // TODO: set correct size for delay
-    pub fn to_transpartent<'b, D>(
-        self,
+    pub fn to_transpartent<'b, D, T>(
+        mut self,
         delay: &'b mut D,
         cmd_char: u8,
         guard_time: u16,
-    ) -> XBeeTransparent<'a, 'b, U, D>
+        persist: bool,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<XBeeTransparent<'a, 'b, U, D>, ApiError<E>>
     where
         D: DelayMs<u16>,
+        T: CountDown,
+        T::Time: Clone,
+    {
+        self.set_ap(0, persist, &mut |_| {}, timer, timeout)?;
+        Ok(XBeeTransparent::new(self.serial, delay, cmd_char, guard_time))
+    }
+
+    /// Queues `params` with 0x09 frames and applies them all at once with `AC`,
+    /// then persists them with `WR` if `persist` is set.
+    /// Fails with `ApiError::AtCommand` naming the first command the module rejected.
+    /// The ones queued before it stay pending, and the next frame that isn't queued applies
+    /// them, even a `get`. To discard them, queue their previous values again,
+    /// or reset the module with `FR`, which drops anything not applied yet.
+    /// Each response has to arrive within `timeout`, unrelated frames are passed to `handler`.
+    pub fn apply_params<H, T>(
+        &mut self,
+        params: &[([u8; 2], &[u8])],
+        persist: bool,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
     {
-        // TODO: AT command
-        XBeeTransparent::new(self.serial, delay, cmd_char, guard_time)
+        for &(at_cmd, value) in params {
+            self.at_command(true, at_cmd, value, handler, timer, timeout.clone())?;
+        }
+        self.at_command(false, [b'A', b'C'], &[], handler, timer, timeout.clone())?;
+        if persist {
+            self.at_command(false, [b'W', b'R'], &[], handler, timer, timeout)?;
+        }
+        Ok(())
+    }
+
+    fn at_command<H, T>(
+        &mut self,
+        queue: bool,
+        at_cmd: [u8; 2],
+        params: &[u8],
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), ApiError<E>>
+    where
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+    {
+        let frame = |frame_id| if queue {
+            frame::Outbound::AtCommandQueueParam {
+                frame_id,
+                at_cmd,
+                params,
+            }
+        } else {
+            frame::Outbound::AtCommand {
+                frame_id,
+                at_cmd,
+                params,
+            }
+        };
+        let response = self.send_and_wait(frame, handler, timer, timeout)?;
+        at_status(at_cmd, response)
+    }
+
+    // the frame `fill` completed, its frame ID released
+    fn take_frame(&mut self) -> Result<frame::Inbound, ApiError<E>> {
+        match self.decoder.frame() {
+            | Some(Ok(frame)) => {
+                if let Some(id) = frame.frame_id() {
+                    self.ids.release(id);
+                }
+                Ok(frame)
+            }
+            | Some(Err(e)) => Err(e.into()),
+            | None => Err(serializer::FrameError::Incomplete.into()),
+        }
+    }
+
+    // reads whatever is available, until a frame is complete
+    fn fill(&mut self) -> nb::Result<(), ApiError<E>> {
+        loop {
+            match self.serial.read() {
+                | Ok(byte) => if self.decoder.push_byte(byte) {
+                    return Ok(());
+                },
+                | Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                | Err(nb::Error::Other(e)) => {
+                    return Err(nb::Error::Other(serializer::DeserializationError::Other(e).into()))
+                }
+            }
+        }
+    }
+}
+
+fn at_status<E>(at_cmd: [u8; 2], response: frame::Inbound) -> Result<(), ApiError<E>> {
+    match response {
+        | frame::Inbound::AtCommandResponse {
+            status: frame::AtCommandStatus::Ok,
+            ..
+        } => Ok(()),
+        | frame::Inbound::AtCommandResponse { status, .. } => {
+            Err(ApiError::AtCommand { at_cmd, status })
+        }
+        | _ => Err(ApiError::UnexpectedResponse),
     }
 }
 
-// impl<'a, E, U> XBeeApi for XBeeApiUart<'a, U>
-// where
-//     U: Read<u8, Error = E> + BlockingWrite<u8, Error = E> + Write<u8, Error = E>,
-// {
-//     type Error = serializer::SerializationError<E>;
+impl<'a, E, U> XBeeApi for XBeeApiUart<'a, U>
+where
+    U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+{
+    type Error = ApiError<E>;
+
+    fn send(&mut self, frame: &frame::Outbound) -> Result<(), Self::Error> {
+        let serial = &mut self.serial;
+        serializer::write_mode(
+            &mut |b| serial.bwrite_all(&[b]).map_err(nb::Error::Other),
+            frame,
+            self.mode,
+        )?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<frame::Inbound, Self::Error> {
+        block!(self.fill())?;
+        self.take_frame()
+    }
+
+    fn poll_receive(&mut self) -> nb::Result<frame::Inbound, Self::Error> {
+        self.fill()?;
+        self.take_frame().map_err(nb::Error::Other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mock::{Delay, Module, PollTimer, Script};
+    use serializer::ApiMode;
+
+    #[test]
+    fn frame_ids_test() {
+        let mut ids = FrameIds::new();
+        assert_eq!(ids.allocate(), Some(1));
+        assert_eq!(ids.allocate(), Some(2));
+        ids.release(1);
+        for id in 3..=0xFF {
+            assert_eq!(ids.allocate(), Some(id));
+        }
+        // wraps around skipping 0 and the in-flight 2
+        assert_eq!(ids.allocate(), Some(1));
+        assert_eq!(ids.allocate(), None);
+        ids.release(0x80);
+        assert!(!ids.is_in_flight(0x80));
+        assert_eq!(ids.allocate(), Some(0x80));
+    }
+
+    #[test]
+    fn at_reply_parse_test() {
+        assert_eq!(AtReply::parse(b"OK"), Ok(AtReply::Ok));
+        assert_eq!(AtReply::parse(b"ERROR"), Ok(AtReply::Error));
+        assert_eq!(AtReply::parse(b""), Ok(AtReply::Empty));
+        assert_eq!(AtReply::parse(b"13A200").unwrap().as_hex(), Some(0x13A200));
+        assert_eq!(AtReply::parse(b"0013A20040A1B2C3").unwrap().as_hex(), Some(0x0013A20040A1B2C3));
+        assert_eq!(AtReply::parse(b"0012"), Ok(AtReply::Value("0012")));
+        assert_eq!(AtReply::parse(b"1013A20040A1B2C3D").unwrap().as_hex(), None);
+        assert_eq!(AtReply::parse(b"router 1"), Ok(AtReply::Value("router 1")));
+        assert_eq!(AtReply::parse(b"router 1").unwrap().as_hex(), None);
+        assert_eq!(AtReply::parse(b"+1").unwrap().as_hex(), None);
+        assert_eq!(AtReply::parse(&[b'x', 0xFF]), Err(0xFF));
+    }
+
+    #[test]
+    fn at_command_test() {
+        let mut uart = Script::new(b"OK\rED\rERROR\rOK\r");
+        {
+            let mut delay = Delay;
+            let mut xbee = XBeeTransparent::new(&mut uart, &mut delay, b'+', 1000);
+            let mut timer = PollTimer { remaining: 0 };
+            let mut buffer = [0; 20];
+            assert_eq!(
+                xbee.at_command(*b"ID", b"3332", &mut buffer, &mut timer, 10)
+                    .unwrap(),
+                AtReply::Ok
+            );
+            // the node identifier stays text
+            assert_eq!(
+                xbee.at_command(*b"NI", &[], &mut buffer, &mut timer, 10)
+                    .unwrap(),
+                AtReply::Value("ED")
+            );
+            match xbee.at_command(*b"XX", &[], &mut buffer, &mut timer, 10) {
+                | Err(CommandError::Error) => {}
+                | res => panic!("{:?}", res),
+            }
+            xbee.exit_command_mode(&mut timer, 10).unwrap();
+            match xbee.at_command(*b"ID", &[], &mut buffer, &mut timer, 10) {
+                | Err(CommandError::Timeout) => {}
+                | res => panic!("{:?}", res),
+            }
+        }
+        assert_eq!(uart.sent(), &b"ATID3332\rATNI\rATXX\rATCN\rATID\r"[..]);
+    }
+
+    #[test]
+    fn at_command_lines_test() {
+        // two records, then the empty line ending the reply, then whatever comes next
+        let mut uart =
+            Script::new(b"7D84\r0013A200\r40522BAA\rL1\r\r1234\r0013A200\r40522BBB\rED\r\r\rOK\r");
+        let mut lines = 0;
+        let mut records = 0;
+        {
+            let mut delay = Delay;
+            let mut xbee = XBeeTransparent::new(&mut uart, &mut delay, b'+', 1000);
+            let mut timer = PollTimer { remaining: 0 };
+            let mut buffer = [0; 20];
+            xbee.at_command_lines(
+                *b"ND",
+                &[],
+                &mut buffer,
+                &mut |reply| match reply {
+                    | AtReply::Empty => records += 1,
+                    | _ => lines += 1,
+                },
+                &mut timer,
+                10,
+            ).unwrap();
+        }
+        assert_eq!((lines, records), (8, 2));
+        assert_eq!(uart.pending(), b"OK\r");
+        assert_eq!(uart.sent(), b"ATND\r");
+
+        // no node answered
+        let mut uart = Script::new(b"\r");
+        let mut delay = Delay;
+        let mut xbee = XBeeTransparent::new(&mut uart, &mut delay, b'+', 1000);
+        let mut timer = PollTimer { remaining: 0 };
+        xbee.at_command_lines(*b"ND", &[], &mut [0; 20], &mut |_| panic!(), &mut timer, 10)
+            .unwrap();
+    }
 
-//     fn send(&mut self, frame : &frame::Outbound) -> Result<(), Self::Error> {
-//         serializer::serialize(&mut |b| self.serial.write(b), frame)?;
-//         Ok(())
-//     }
+    #[test]
+    fn detect_mode_test() {
+        let mut timer = PollTimer { remaining: 0 };
+        for &(ap, driver, detected) in &[
+            (1, ApiMode::Escaped, ApiMode::Unescaped),
+            (2, ApiMode::Unescaped, ApiMode::Escaped),
+        ] {
+            let mut module = Module::new(&[(*b"AP", ap)]);
+            let mut buffer = [0; 64];
+            let mut xbee = XBeeApiUart::new(&mut module, driver, &mut buffer);
+            assert_eq!(xbee.detect_mode(&mut |_| {}, &mut timer, 10).unwrap(), detected);
+            assert_eq!(xbee.mode(), detected);
+        }
 
-//     fn receive(&mut self) -> Result<frame::Inbound, E> {
-//     }
-// }
+        // nothing answers in transparent mode
+        let mut module = Module::new(&[(*b"AP", 0)]);
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+        match xbee.detect_mode(&mut |_| {}, &mut timer, 10) {
+            | Err(ApiError::Timeout) => {}
+            | res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn switch_mode_test() {
+        let mut module = Module::new(&[(*b"AP", 1)]);
+        let mut timer = PollTimer { remaining: 0 };
+        {
+            let mut buffer = [0; 64];
+            let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+            xbee.switch_mode(ApiMode::Escaped, false, &mut |_| {}, &mut timer, 10).unwrap();
+            assert_eq!(xbee.mode(), ApiMode::Escaped);
+            // frame ID 0x13 is escaped now
+            for _ in 0..0x0F {
+                let id = xbee.frame_ids().allocate().unwrap();
+                xbee.frame_ids().release(id);
+            }
+            assert_eq!(xbee.get(at::Ap, &mut |_| {}, &mut timer, 10).unwrap(), 2);
+        }
+        assert_eq!(module.ap, 2);
+
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Escaped, &mut buffer);
+        xbee.switch_mode(ApiMode::Unescaped, true, &mut |_| {}, &mut timer, 10).unwrap();
+        assert_eq!(xbee.get(at::Ap, &mut |_| {}, &mut timer, 10).unwrap(), 1);
+    }
+
+    #[test]
+    fn transparent_api_test() {
+        let mut module = Module::new(&[(*b"AP", 0)]);
+        let mut delay = Delay;
+        let mut timer = PollTimer { remaining: 0 };
+        {
+            let mut buffer = [0; 64];
+            let xbee = XBeeTransparent::new(&mut module, &mut delay, b'+', 1000);
+            let mut xbee = xbee
+                .to_api(ApiMode::Escaped, true, &mut buffer, &mut timer, 10)
+                .unwrap();
+            assert_eq!(xbee.get(at::Ap, &mut |_| {}, &mut timer, 10).unwrap(), 2);
+            let mut xbee = xbee
+                .to_transpartent(&mut delay, b'+', 1000, false, &mut timer, 10)
+                .unwrap();
+            let mut reply = [0; 8];
+            xbee.enter_command_mode(&mut timer, 10).unwrap();
+            assert_eq!(
+                xbee.at_command(*b"AP", &[], &mut reply, &mut timer, 10)
+                    .unwrap()
+                    .as_hex(),
+                Some(0)
+            );
+        }
+        assert_eq!(module.log(), &[*b"AP", *b"WR", *b"AP", *b"CN", *b"AP", *b"AP", *b"AC", *b"AP"]);
+    }
+
+    #[test]
+    fn start_test() {
+        let mut delay = Delay;
+        let mut timer = PollTimer { remaining: 0 };
+
+        // from transparent mode
+        let mut module = Module::new(&[(*b"AP", 0)]);
+        {
+            let xbee = XBeeTransparent::new(&mut module, &mut delay, b'+', 1000);
+            let mut buffer = [0; 64];
+            let xbee = XBeeApiUart::start(
+                xbee,
+                ApiMode::Unescaped,
+                false,
+                &mut buffer,
+                &mut |_| {},
+                &mut timer,
+                10,
+            )
+            .unwrap();
+            assert_eq!(xbee.mode(), ApiMode::Unescaped);
+        }
+        assert_eq!(module.ap, 1);
+
+        // from the other API mode, with a frame longer than a command mode reply waiting
+        let mut module = Module::new(&[(*b"AP", 2)]);
+        module.respond(&[
+            0x90, 0x00, 0x13, 0xA2, 0x00, 0x40, 0x52, 0x2B, 0xAA, 0x7D, 0x84, 0x01, 0x52, 0x53,
+        ]);
+        {
+            let xbee = XBeeTransparent::new(&mut module, &mut delay, b'+', 1000);
+            let mut buffer = [0; 64];
+            let xbee = XBeeApiUart::start(
+                xbee,
+                ApiMode::Unescaped,
+                false,
+                &mut buffer,
+                &mut |_| {},
+                &mut timer,
+                10,
+            )
+            .unwrap();
+            assert_eq!(xbee.mode(), ApiMode::Unescaped);
+        }
+        assert_eq!(module.ap, 1);
+    }
+}
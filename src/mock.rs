@@ -0,0 +1,412 @@
+//! Test doubles for timers and for a module on the UART, and other test helpers.
+
+use baud::{Reconfigure, RATES};
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::serial::Write as BlockingWrite;
+use embedded_hal::serial::Read;
+use embedded_hal::timer::CountDown;
+use nb;
+
+/// Expires after a number of polls
+pub struct PollTimer {
+    pub remaining: u32,
+}
+
+impl CountDown for PollTimer {
+    type Time = u32;
+
+    fn start<T: Into<u32>>(&mut self, count: T) {
+        self.remaining = count.into();
+    }
+
+    fn wait(&mut self) -> nb::Result<(), ::void::Void> {
+        if self.remaining == 0 {
+            Ok(())
+        } else {
+            self.remaining -= 1;
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+// xorshift, so that tests are repeatable without pulling in a dependency
+pub fn random(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+pub struct Delay;
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, _: u16) {}
+}
+
+/// Replays canned bytes to the driver and records what it writes
+pub struct Script {
+    rx: &'static [u8],
+    rx_pos: usize,
+    tx: [u8; 128],
+    tx_len: usize,
+}
+
+impl Script {
+    pub fn new(rx: &'static [u8]) -> Script {
+        Script {
+            rx,
+            rx_pos: 0,
+            tx: [0; 128],
+            tx_len: 0,
+        }
+    }
+
+    pub fn sent(&self) -> &[u8] {
+        &self.tx[..self.tx_len]
+    }
+
+    /// Bytes not read yet
+    pub fn pending(&self) -> &[u8] {
+        &self.rx[self.rx_pos..]
+    }
+}
+
+impl Read<u8> for Script {
+    type Error = ();
+
+    fn read(&mut self) -> nb::Result<u8, ()> {
+        let byte = *self.rx.get(self.rx_pos).ok_or(nb::Error::WouldBlock)?;
+        self.rx_pos += 1;
+        Ok(byte)
+    }
+}
+
+impl BlockingWrite<u8> for Script {
+    type Error = ();
+
+    fn bwrite_all(&mut self, buffer: &[u8]) -> Result<(), ()> {
+        self.tx[self.tx_len..self.tx_len + buffer.len()].copy_from_slice(buffer);
+        self.tx_len += buffer.len();
+        Ok(())
+    }
+
+    fn bflush(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/// Answers AT commands like a module in the mode `AP` selects, with numeric parameters only:
+/// in API mode 1 or 2, or in command mode after `+++` in transparent mode.
+/// Only hears the host at the rate `BD` selects. Both apply with `AC`, `ATCN`, or `WR` in API mode.
+/// Values queued with 0x09 frames are held until the next 0x08 frame, usually `AC` or `WR`.
+/// Queries of unknown parameters fail with an invalid command status or "ERROR",
+/// anything else without a value (`AC`, `WR`, ...) just succeeds.
+/// A command can be scripted to answer with several responses instead.
+pub struct Module {
+    params: [([u8; 2], u64); 16],
+    params_len: usize,
+    rx: [u8; 512],
+    rx_len: usize,
+    rx_pos: usize,
+    tx: [u8; 64],
+    tx_len: usize,
+    escaped: bool,
+    queued: [([u8; 2], u64); 16],
+    queued_len: usize,
+    applied_by: [[u8; 2]; 16],
+    applied_len: usize,
+    /// Commands received, in order
+    pub log: [[u8; 2]; 64],
+    pub log_len: usize,
+    pub baud_rate: u32,
+    host_rate: u32,
+    /// `AP` in effect
+    pub ap: u64,
+    command_mode: bool,
+    plus: usize,
+    script: Option<([u8; 2], &'static [&'static [u8]])>,
+}
+
+impl Module {
+    pub fn new(params: &[([u8; 2], u64)]) -> Module {
+        let mut module = Module {
+            params: [([0; 2], 0); 16],
+            params_len: 0,
+            rx: [0; 512],
+            rx_len: 0,
+            rx_pos: 0,
+            tx: [0; 64],
+            tx_len: 0,
+            escaped: false,
+            queued: [([0; 2], 0); 16],
+            queued_len: 0,
+            applied_by: [[0; 2]; 16],
+            applied_len: 0,
+            log: [[0; 2]; 64],
+            log_len: 0,
+            baud_rate: 9600,
+            host_rate: 9600,
+            ap: 1,
+            command_mode: false,
+            plus: 0,
+            script: None,
+        };
+        for &(at_cmd, value) in params {
+            module.store(at_cmd, value);
+        }
+        module.apply();
+        module
+    }
+
+    pub fn log(&self) -> &[[u8; 2]] {
+        &self.log[..self.log_len]
+    }
+
+    /// Commands that applied queued values, in order
+    pub fn applied_by(&self) -> &[[u8; 2]] {
+        &self.applied_by[..self.applied_len]
+    }
+
+    pub fn param(&self, at_cmd: [u8; 2]) -> Option<u64> {
+        self.params[..self.params_len]
+            .iter()
+            .find(|p| p.0 == at_cmd)
+            .map(|p| p.1)
+    }
+
+    /// Queues a frame for the driver to receive,
+    /// `data` being everything between length and checksum
+    pub fn respond(&mut self, data: &[u8]) {
+        let len = data.len();
+        self.push_rx(&[0x7E]);
+        self.push_api(&[(len >> 8) as u8, len as u8]);
+        self.push_api(data);
+        let checksum = 0xFF - data.iter().fold(0, |acc: u8, &b| acc.wrapping_add(b));
+        self.push_api(&[checksum]);
+    }
+
+    /// Answers `at_cmd` with one successful response per entry of `responses`, like `ND`
+    pub fn script(&mut self, at_cmd: [u8; 2], responses: &'static [&'static [u8]]) {
+        self.script = Some((at_cmd, responses));
+    }
+
+    fn push_rx(&mut self, data: &[u8]) {
+        self.rx[self.rx_len..self.rx_len + data.len()].copy_from_slice(data);
+        self.rx_len += data.len();
+    }
+
+    // escaped in API mode 2
+    fn push_api(&mut self, data: &[u8]) {
+        for &b in data {
+            match b {
+                | 0x7E | 0x7D | 0x11 | 0x13 if self.ap == 2 => self.push_rx(&[0x7D, b ^ 0x20]),
+                | b => self.push_rx(&[b]),
+            }
+        }
+    }
+
+    pub fn store(&mut self, at_cmd: [u8; 2], value: u64) {
+        match self.params[..self.params_len].iter().position(|p| p.0 == at_cmd) {
+            | Some(i) => self.params[i].1 = value,
+            | None => {
+                self.params[self.params_len] = (at_cmd, value);
+                self.params_len += 1;
+            }
+        }
+    }
+
+    fn apply(&mut self) {
+        if let Some(bd) = self.param(*b"BD") {
+            self.baud_rate = RATES.get(bd as usize).cloned().unwrap_or(bd as u32);
+        }
+        self.ap = self.param(*b"AP").unwrap_or(1);
+    }
+
+    fn record(&mut self, at_cmd: [u8; 2]) {
+        self.log[self.log_len] = at_cmd;
+        self.log_len += 1;
+    }
+
+    fn api_byte(&mut self, b: u8) {
+        if self.tx_len == 0 && b != 0x7E {
+            return;
+        }
+        let b = if self.tx_len == 0 || self.ap != 2 {
+            b
+        } else if self.escaped {
+            self.escaped = false;
+            b ^ 0x20
+        } else if b == 0x7D {
+            self.escaped = true;
+            return;
+        } else {
+            b
+        };
+        self.tx[self.tx_len] = b;
+        self.tx_len += 1;
+        let len = (self.tx[1] as usize) << 8 | self.tx[2] as usize;
+        if self.tx_len > 3 && self.tx_len == len + 4 {
+            self.frame();
+        }
+    }
+
+    fn frame(&mut self) {
+        let len = ((self.tx[1] as usize) << 8) | self.tx[2] as usize;
+        let mut data = [0; 64];
+        data[..len].copy_from_slice(&self.tx[3..3 + len]);
+        let data = &data[..len];
+        self.tx_len = 0;
+        if data[0] != 0x08 && data[0] != 0x09 {
+            return;
+        }
+        let (frame_id, at_cmd, params) = (data[1], [data[2], data[3]], &data[4..]);
+        self.record(at_cmd);
+        let mut response = [0x88, frame_id, at_cmd[0], at_cmd[1], 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        if data[0] == 0x09 && !params.is_empty() {
+            let value = params.iter().fold(0, |acc, &b| acc << 8 | b as u64);
+            self.queued[self.queued_len] = (at_cmd, value);
+            self.queued_len += 1;
+            self.respond(&response[..5]);
+            return;
+        }
+        if data[0] == 0x08 && self.queued_len > 0 {
+            for i in 0..self.queued_len {
+                let (at_cmd, value) = self.queued[i];
+                self.store(at_cmd, value);
+            }
+            self.queued_len = 0;
+            self.applied_by[self.applied_len] = at_cmd;
+            self.applied_len += 1;
+        }
+        if let Some((_, responses)) = self.script.filter(|s| s.0 == at_cmd) {
+            let mut scripted = [0; 64];
+            scripted[..5].copy_from_slice(&response[..5]);
+            for data in responses {
+                scripted[5..5 + data.len()].copy_from_slice(data);
+                self.respond(&scripted[..5 + data.len()]);
+            }
+        } else if !params.is_empty() {
+            let value = params.iter().fold(0, |acc, &b| acc << 8 | b as u64);
+            self.store(at_cmd, value);
+            self.respond(&response[..5]);
+        } else if let Some(value) = self.param(at_cmd) {
+            // shortest big-endian form
+            let bytes = 8 - (value.leading_zeros() as usize / 8).min(7);
+            for i in 0..bytes {
+                response[5 + i] = (value >> (8 * (bytes - 1 - i))) as u8;
+            }
+            self.respond(&response[..5 + bytes]);
+        } else if is_exec(at_cmd) {
+            self.respond(&response[..5]);
+            self.apply();
+        } else {
+            response[4] = 0x02;
+            self.respond(&response[..5]);
+        }
+    }
+
+    fn command_byte(&mut self, b: u8) {
+        if !self.command_mode {
+            self.plus = if b == b'+' { self.plus + 1 } else { 0 };
+            if self.plus == 3 {
+                self.plus = 0;
+                self.command_mode = true;
+                self.push_rx(b"OK\r");
+            }
+            return;
+        }
+        if b != b'\r' {
+            self.tx[self.tx_len] = b;
+            self.tx_len += 1;
+            return;
+        }
+        let mut line = [0; 64];
+        line[..self.tx_len].copy_from_slice(&self.tx[..self.tx_len]);
+        let line = &line[..self.tx_len];
+        self.tx_len = 0;
+        if line.len() < 4 || &line[..2] != b"AT" {
+            self.push_rx(b"ERROR\r");
+            return;
+        }
+        let (at_cmd, param) = ([line[2], line[3]], &line[4..]);
+        self.record(at_cmd);
+        if !param.is_empty() {
+            match param.iter().try_fold(0, |acc, &b| {
+                (b as char).to_digit(16).map(|d| acc << 4 | d as u64)
+            }) {
+                | Some(value) => {
+                    self.store(at_cmd, value);
+                    self.push_rx(b"OK\r");
+                }
+                | None => self.push_rx(b"ERROR\r"),
+            }
+        } else if let Some(value) = self.param(at_cmd) {
+            let digits = b"0123456789ABCDEF";
+            let len = (16 - value.leading_zeros() as usize / 4).max(1);
+            for i in 0..len {
+                let digit = digits[(value >> (4 * (len - 1 - i)) & 0xF) as usize];
+                self.push_rx(&[digit]);
+            }
+            self.push_rx(b"\r");
+        } else if is_exec(at_cmd) || at_cmd == *b"CN" {
+            self.push_rx(b"OK\r");
+            // `WR` only writes in command mode
+            if at_cmd == *b"CN" || at_cmd == *b"AC" {
+                self.command_mode = at_cmd != *b"CN";
+                self.apply();
+            }
+        } else {
+            self.push_rx(b"ERROR\r");
+        }
+    }
+}
+
+fn is_exec(at_cmd: [u8; 2]) -> bool {
+    match &at_cmd {
+        | b"AC" | b"WR" | b"RE" | b"FR" | b"NR" | b"CB" | b"SI" => true,
+        | _ => false,
+    }
+}
+
+impl Read<u8> for Module {
+    type Error = ();
+
+    fn read(&mut self) -> nb::Result<u8, ()> {
+        if self.rx_pos == self.rx_len {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.rx_pos += 1;
+        Ok(self.rx[self.rx_pos - 1])
+    }
+}
+
+impl BlockingWrite<u8> for Module {
+    type Error = ();
+
+    fn bwrite_all(&mut self, buffer: &[u8]) -> Result<(), ()> {
+        for &b in buffer {
+            if self.host_rate != self.baud_rate {
+                continue;
+            }
+            if self.ap == 0 {
+                self.command_byte(b);
+            } else {
+                self.api_byte(b);
+            }
+        }
+        Ok(())
+    }
+
+    fn bflush(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+impl Reconfigure for Module {
+    type Error = ();
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), ()> {
+        self.host_rate = baud_rate;
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Declarative module configuration, applied by changing only what differs.
+
+use embedded_hal::blocking::serial::Write as BlockingWrite;
+use embedded_hal::serial::Read;
+use embedded_hal::timer::CountDown;
+use heapless::{consts, Vec};
+
+use at::{self, Command};
+use baud;
+use frame;
+use serializer::ApiMode;
+use {ApiError, XBeeApiUart};
+
+/// Desired module configuration, `None` leaves a parameter as it is.
+/// Encryption keys are write-only, so they can't be compared and aren't part of it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// `ID`, 0 to join any PAN
+    pub pan_id: Option<u64>,
+    /// `SC`
+    pub scan_channels: Option<u16>,
+    /// `AP`, the driver's framing follows
+    pub api_mode: Option<ApiMode>,
+    /// Set with `BD`, the UART has to be reconfigured to match once it changed
+    pub baud_rate: Option<u32>,
+    /// `EE`
+    pub encryption: Option<bool>,
+    /// `EO`
+    pub encryption_options: Option<u8>,
+    /// `SM`
+    pub sleep_mode: Option<u8>,
+    /// Pin modes, indexed by DIO number: `D0`-`D9`, then `P0`-`P2`
+    pub dio: [Option<u8>; 13],
+}
+
+/// Parameters `Profile::apply` changed, in the order they were applied
+#[derive(Debug)]
+pub struct Report {
+    changed: Vec<[u8; 2], consts::U32>,
+}
+
+impl Report {
+    pub fn changed(&self) -> &[[u8; 2]] {
+        &self.changed
+    }
+
+    pub fn contains(&self, at_cmd: [u8; 2]) -> bool {
+        self.changed.contains(&at_cmd)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty()
+    }
+
+    fn push(&mut self, at_cmd: [u8; 2]) {
+        // there is room for every parameter of a profile
+        let _ = self.changed.push(at_cmd);
+    }
+}
+
+impl Profile {
+    /// Reads the current values, then queues the ones that differ and applies them all at once
+    /// with `AC`, or `WR` if `persist` is set. Any frame other than a queued parameter would
+    /// apply them early, so nothing else is sent in between. If the module rejects a value,
+    /// the ones queued before it stay pending, see `XBeeApiUart::apply_params` for discarding them.
+    /// Running it again changes nothing.
+    /// The API mode is switched next, and the baud rate last, as either changes how
+    /// the module talks to us. A baud rate `baud::bd` has no value for fails with `InvalidParam`
+    /// before anything is sent. `timeout` applies to each response.
+    pub fn apply<'a, E, U, H, T>(
+        &self,
+        xbee: &mut XBeeApiUart<'a, U>,
+        persist: bool,
+        handler: &mut H,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<Report, ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        let bd = match self.baud_rate {
+            | Some(baud_rate) => Some(baud::bd(baud_rate).ok_or(ApiError::AtCommand {
+                at_cmd: at::Bd::AT,
+                status: frame::AtCommandStatus::InvalidParam,
+            })?),
+            | None => None,
+        };
+        let mut report = Report { changed: Vec::new() };
+        self.visit(&mut Pass {
+            queue: false,
+            xbee: &mut *xbee,
+            report: &mut report,
+            handler: &mut *handler,
+            timer: &mut *timer,
+            timeout: timeout.clone(),
+        })?;
+        if !report.is_empty() {
+            self.visit(&mut Pass {
+                queue: true,
+                xbee: &mut *xbee,
+                report: &mut report,
+                handler: &mut *handler,
+                timer: &mut *timer,
+                timeout: timeout.clone(),
+            })?;
+            xbee.commit(persist, handler, timer, timeout.clone())?;
+        }
+        match self.api_mode {
+            | Some(mode) if mode != xbee.mode() => {
+                xbee.switch_mode(mode, persist, handler, timer, timeout.clone())?;
+                report.push(at::Ap::AT);
+            }
+            | _ => {}
+        }
+        if let Some(bd) = bd {
+            if xbee.get(at::Bd, handler, timer, timeout.clone())? != bd {
+                xbee.queue(at::Bd, &bd, handler, timer, timeout.clone())?;
+                xbee.commit(persist, handler, timer, timeout)?;
+                report.push(at::Bd::AT);
+            }
+        }
+        Ok(report)
+    }
+
+    // the parameters applied together
+    fn visit<'x, 'a, E, U, H, T>(&self, pass: &mut Pass<'x, 'a, U, H, T>) -> Result<(), ApiError<E>>
+    where
+        U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+        H: FnMut(frame::Inbound),
+        T: CountDown,
+        T::Time: Clone,
+    {
+        pass.param(at::Id, &self.pan_id)?;
+        pass.param(at::Sc, &self.scan_channels)?;
+        pass.param(at::Ee, &self.encryption)?;
+        pass.param(at::Eo, &self.encryption_options)?;
+        pass.param(at::Sm, &self.sleep_mode)?;
+        pass.param(at::D0, &self.dio[0])?;
+        pass.param(at::D1, &self.dio[1])?;
+        pass.param(at::D2, &self.dio[2])?;
+        pass.param(at::D3, &self.dio[3])?;
+        pass.param(at::D4, &self.dio[4])?;
+        pass.param(at::D5, &self.dio[5])?;
+        pass.param(at::D6, &self.dio[6])?;
+        pass.param(at::D7, &self.dio[7])?;
+        pass.param(at::D8, &self.dio[8])?;
+        pass.param(at::D9, &self.dio[9])?;
+        pass.param(at::P0, &self.dio[10])?;
+        pass.param(at::P1, &self.dio[11])?;
+        pass.param(at::P2, &self.dio[12])
+    }
+}
+
+// reads the parameters, noting the ones that differ from the profile,
+// or with `queue` set, queues the noted ones
+struct Pass<'x, 'a: 'x, U: 'a, H: 'x, T: 'x + CountDown> {
+    queue: bool,
+    xbee: &'x mut XBeeApiUart<'a, U>,
+    report: &'x mut Report,
+    handler: &'x mut H,
+    timer: &'x mut T,
+    timeout: T::Time,
+}
+
+impl<'x, 'a, E, U, H, T> Pass<'x, 'a, U, H, T>
+where
+    U: Read<u8, Error = E> + BlockingWrite<u8, Error = E>,
+    H: FnMut(frame::Inbound),
+    T: CountDown,
+    T::Time: Clone,
+{
+    fn param<C>(&mut self, cmd: C, desired: &Option<C::Value>) -> Result<(), ApiError<E>>
+    where
+        C: at::Get + at::Set + Copy,
+        C::Value: PartialEq,
+    {
+        if let Some(ref value) = *desired {
+            if self.queue {
+                if self.report.contains(C::AT) {
+                    self.xbee.queue(cmd, value, self.handler, self.timer, self.timeout.clone())?;
+                }
+            } else {
+                let current = self.xbee.get(cmd, self.handler, self.timer, self.timeout.clone())?;
+                if current != *value {
+                    self.report.push(C::AT);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mock::{Module, PollTimer};
+
+    #[test]
+    fn apply_test() {
+        let mut module = Module::new(&[(*b"ID", 0), (*b"SC", 0x7FFF), (*b"D0", 1), (*b"BD", 3)]);
+        let mut dio = [None; 13];
+        dio[0] = Some(3);
+        let profile = Profile {
+            pan_id: Some(0x1234),
+            scan_channels: Some(0x7FFF),
+            baud_rate: Some(9600),
+            dio,
+            ..Profile::default()
+        };
+        {
+            let mut buffer = [0; 64];
+            let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+            let mut timer = PollTimer { remaining: 0 };
+            let report = profile.apply(&mut xbee, true, &mut |_| {}, &mut timer, 10).unwrap();
+            assert_eq!(report.changed(), &[*b"ID", *b"D0"]);
+            assert!(report.contains(*b"D0"));
+            assert!(!report.contains(*b"BD"));
+        }
+        assert_eq!(module.param(*b"ID"), Some(0x1234));
+        assert_eq!(module.param(*b"D0"), Some(3));
+        assert_eq!(module.log(), &[*b"ID", *b"SC", *b"D0", *b"ID", *b"D0", *b"WR", *b"BD"]);
+        // all at once
+        assert_eq!(module.applied_by(), &[*b"WR"]);
+
+        // nothing left to change
+        let mut buffer = [0; 64];
+        let mut xbee = XBeeApiUart::new(&mut module, ApiMode::Unescaped, &mut buffer);
+        let mut timer = PollTimer { remaining: 0 };
+        let report = profile.apply(&mut xbee, true, &mut |_| {}, &mut timer, 10).unwrap();
+        assert!(report.is_empty());
+
+        // a BD value rather than a rate
+        let profile = Profile {
+            baud_rate: Some(3),
+            ..profile
+        };
+        match profile.apply(&mut xbee, true, &mut |_| {}, &mut timer, 10) {
+            | Err(ApiError::AtCommand {
+                at_cmd,
+                status: frame::AtCommandStatus::InvalidParam,
+            }) => assert_eq!(at_cmd, *b"BD"),
+            | res => panic!("{:?}", res),
+        }
+    }
+}